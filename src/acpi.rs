@@ -0,0 +1,461 @@
+//! ACPI 테이블 탐색
+//!
+//! 펌웨어가 메모리에 남겨둔 RSDP -> RSDT/XSDT -> 개별 테이블(FADT, MADT, HPET ...) 순서로 따라갑니다.
+//! 테이블은 물리 주소로만 알려지기 때문에, 물리 메모리 전체가 매핑된 가상 주소 오프셋이 있어야 읽을 수 있습니다.
+use core::ptr;
+use spin::Once;
+use x86_64::{PhysAddr, VirtAddr};
+
+/*
+    RSDP (Root System Description Pointer)는 BIOS 시스템에서 다음 두 곳 중 한 곳의 16바이트 경계에 놓여 있습니다.
+    1. EBDA (Extended BIOS Data Area)의 처음 1KiB. EBDA의 세그먼트 주소는 물리 주소 0x40E에 적혀 있습니다.
+    2. 0xE0000 ~ 0xFFFFF 사이의 BIOS 읽기 전용 영역
+*/
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const EBDA_POINTER: u64 = 0x40E;
+const BIOS_AREA_START: u64 = 0xE0000;
+const BIOS_AREA_END: u64 = 0x100000;
+
+const SDT_HEADER_SIZE: u64 = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    RsdpNotFound,
+    InvalidChecksum([u8; 4]),
+    /// 루트 테이블의 주소나 길이가 잘못되었습니다.
+    InvalidRootTable,
+}
+
+/// 모든 ACPI 테이블이 공유하는 헤더
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+struct Acpi {
+    physical_memory_offset: VirtAddr,
+    root_table: PhysAddr,
+    // ACPI 2.0 이상의 XSDT는 64비트, RSDT는 32비트 포인터 배열을 가지고 있습니다.
+    extended: bool,
+}
+
+static ACPI: Once<Acpi> = Once::new();
+
+/// RSDP를 찾아 루트 테이블을 기록합니다.
+///
+/// # Safety
+/// 물리 메모리 전체가 `physical_memory_offset`부터 매핑되어 있어야 합니다.
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> Result<(), AcpiError> {
+    let rsdp = find_rsdp(physical_memory_offset).ok_or(AcpiError::RsdpNotFound)?;
+    let virt = |addr: u64| (physical_memory_offset + addr).as_ptr::<u8>();
+
+    let revision = ptr::read(virt(rsdp + 15));
+    let (root_table, extended) = if revision >= 2 {
+        (ptr::read_unaligned(virt(rsdp + 24) as *const u64), true)
+    } else {
+        (u64::from(ptr::read_unaligned(virt(rsdp + 16) as *const u32)), false)
+    };
+
+    let root_table = PhysAddr::try_new(root_table).map_err(|_| AcpiError::InvalidRootTable)?;
+    let header: SdtHeader = ptr::read_unaligned(virt(root_table.as_u64()) as *const SdtHeader);
+    if u64::from(header.length) < SDT_HEADER_SIZE {
+        return Err(AcpiError::InvalidRootTable);
+    }
+    let bytes = core::slice::from_raw_parts(virt(root_table.as_u64()), header.length as usize);
+    if !checksum_ok(bytes) {
+        return Err(AcpiError::InvalidChecksum(header.signature));
+    }
+
+    ACPI.call_once(|| Acpi {
+        physical_memory_offset,
+        root_table,
+        extended,
+    });
+    Ok(())
+}
+
+unsafe fn find_rsdp(physical_memory_offset: VirtAddr) -> Option<u64> {
+    let virt = |addr: u64| (physical_memory_offset + addr).as_ptr::<u8>();
+
+    let ebda = u64::from(ptr::read_unaligned(virt(EBDA_POINTER) as *const u16)) << 4;
+    let candidates = (ebda..ebda + 1024).step_by(16)
+        .chain((BIOS_AREA_START..BIOS_AREA_END).step_by(16));
+
+    for addr in candidates {
+        let signature = core::slice::from_raw_parts(virt(addr), RSDP_SIGNATURE.len());
+        // ACPI 1.0 RSDP의 크기는 20바이트이며 이 부분의 체크섬은 모든 버전에서 유효해야 합니다.
+        if signature == RSDP_SIGNATURE && checksum_ok(core::slice::from_raw_parts(virt(addr), 20)) {
+            return Some(addr);
+        }
+    }
+    None
+}
+
+// 테이블의 모든 바이트를 더한 값의 하위 8비트는 0이어야 합니다.
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+impl Acpi {
+    fn virt(&self, addr: PhysAddr) -> *const u8 {
+        (self.physical_memory_offset + addr.as_u64()).as_ptr()
+    }
+
+    unsafe fn read<T: Copy>(&self, addr: PhysAddr) -> T {
+        ptr::read_unaligned(self.virt(addr) as *const T)
+    }
+
+    unsafe fn header(&self, addr: PhysAddr) -> SdtHeader {
+        self.read(addr)
+    }
+
+    unsafe fn table_bytes(&self, addr: PhysAddr) -> &'static [u8] {
+        let header = self.header(addr);
+        core::slice::from_raw_parts(self.virt(addr), header.length as usize)
+    }
+
+    unsafe fn find_table(&self, signature: &[u8; 4]) -> Option<PhysAddr> {
+        root_entries(self.table_bytes(self.root_table), self.extended).find(|addr| {
+            let header = self.header(*addr);
+            &header.signature == signature && checksum_ok(self.table_bytes(*addr))
+        })
+    }
+}
+
+// 루트 테이블의 헤더 뒤에 있는 테이블 주소들. panic 핸들러도 이 경로로 전원을 끄므로 헤더보다 짧은 테이블이나
+// 물리 주소로 쓸 수 없는 항목에서 panic하지 않고 건너뜁니다.
+fn root_entries(root: &[u8], extended: bool) -> impl Iterator<Item = PhysAddr> + '_ {
+    let entry_size = if extended { 8 } else { 4 };
+    root.get(SDT_HEADER_SIZE as usize..)
+        .unwrap_or(&[])
+        .chunks_exact(entry_size)
+        .filter_map(move |entry| {
+            let address = if extended { read_u64(entry, 0) } else { u64::from(read_u32(entry, 0)) };
+            PhysAddr::try_new(address).ok()
+        })
+}
+
+/// 서명(`b"APIC"`, `b"HPET"` 등)으로 테이블을 찾아 헤더의 물리 주소를 반환합니다.
+pub fn find_table(signature: &[u8; 4]) -> Option<PhysAddr> {
+    let acpi = ACPI.r#try()?;
+    unsafe { acpi.find_table(signature) }
+}
+
+/// 찾은 테이블 전체(헤더 포함)를 바이트 슬라이스로 돌려줍니다.
+pub fn table(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    let acpi = ACPI.r#try()?;
+    unsafe { acpi.find_table(signature).map(|addr| acpi.table_bytes(addr)) }
+}
+
+/// 전원 관리에 필요한 FADT(서명 "FACP")의 필드들
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub pm1a_control_block: u32,
+    pub pm1b_control_block: u32,
+    pub century_register: u8,
+    pub dsdt: PhysAddr,
+}
+
+// FADT 안에서 각 필드의 바이트 오프셋
+mod fadt_offset {
+    pub const DSDT: usize = 40;
+    pub const SMI_COMMAND: usize = 48;
+    pub const ACPI_ENABLE: usize = 52;
+    pub const PM1A_CONTROL_BLOCK: usize = 64;
+    pub const PM1B_CONTROL_BLOCK: usize = 68;
+    pub const CENTURY: usize = 108;
+    pub const X_DSDT: usize = 140;
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    bytes.get(offset..offset + 4)
+        .map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from(read_u32(bytes, offset)) | u64::from(read_u32(bytes, offset + 4)) << 32
+}
+
+pub fn fadt() -> Option<Fadt> {
+    let bytes = table(b"FACP")?;
+    // ACPI 2.0 이상에서는 X_DSDT가 우선이고, 0이거나 물리 주소로 쓸 수 없으면 32비트 DSDT 필드를 사용합니다.
+    let dsdt = match PhysAddr::try_new(read_u64(bytes, fadt_offset::X_DSDT)) {
+        Ok(x_dsdt) if !x_dsdt.is_null() => x_dsdt,
+        _ => PhysAddr::new(u64::from(read_u32(bytes, fadt_offset::DSDT))),
+    };
+
+    Some(Fadt {
+        smi_command_port: read_u32(bytes, fadt_offset::SMI_COMMAND),
+        acpi_enable: bytes.get(fadt_offset::ACPI_ENABLE).copied().unwrap_or(0),
+        pm1a_control_block: read_u32(bytes, fadt_offset::PM1A_CONTROL_BLOCK),
+        pm1b_control_block: read_u32(bytes, fadt_offset::PM1B_CONTROL_BLOCK),
+        century_register: bytes.get(fadt_offset::CENTURY).copied().unwrap_or(0),
+        dsdt,
+    })
+}
+
+/// DSDT의 `\_S5` 객체에서 S5(soft off) 상태의 SLP_TYPa, SLP_TYPb 값을 읽습니다.
+pub fn s5_sleep_types(fadt: &Fadt) -> Option<(u16, u16)> {
+    let acpi = ACPI.r#try()?;
+    let dsdt = unsafe { acpi.table_bytes(fadt.dsdt) };
+    dsdt_s5(dsdt)
+}
+
+// panic 핸들러도 이 경로로 전원을 끄므로 헤더보다 짧은 DSDT에서도 panic하지 않고 None을 돌려줍니다.
+fn dsdt_s5(dsdt: &[u8]) -> Option<(u16, u16)> {
+    parse_s5(dsdt.get(SDT_HEADER_SIZE as usize..)?)
+}
+
+/*
+    MADT (Multiple APIC Description Table, 서명 "APIC")
+
+    헤더 뒤에 Local APIC의 물리 주소(u32)와 플래그(u32)가 오고, 그 뒤로 가변 길이 항목들이 이어집니다.
+    각 항목은 (종류, 길이) 두 바이트로 시작합니다.
+    - 1: I/O APIC (ID, MMIO 주소, 담당하는 GSI의 시작 번호)
+    - 2: Interrupt Source Override. ISA IRQ가 다른 GSI로 연결되어 있거나 극성/트리거 방식이 다를 때 사용됩니다.
+    - 5: 64비트 Local APIC 주소
+*/
+pub const MAX_IO_APICS: usize = 4;
+pub const ISA_IRQS: usize = 16;
+
+const MADT_ENTRIES_OFFSET: usize = 44;
+const MADT_PCAT_COMPAT: u32 = 1;
+// 항목 종류별 최소 길이
+const IO_APIC_ENTRY_LENGTH: usize = 12;
+const OVERRIDE_ENTRY_LENGTH: usize = 10;
+const LOCAL_APIC_OVERRIDE_ENTRY_LENGTH: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicEntry {
+    pub id: u8,
+    pub address: u32,
+    pub gsi_base: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    pub source: u8,
+    pub gsi: u32,
+    /// bit 0-1: 극성, bit 2-3: 트리거 방식 (0b11이면 각각 active low, level)
+    pub flags: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    /// 8259 PIC도 함께 장착되어 있는지 여부
+    pub pcat_compat: bool,
+    pub io_apics: [Option<IoApicEntry>; MAX_IO_APICS],
+    /// ISA IRQ 번호로 찾는 override 항목
+    pub overrides: [Option<InterruptOverride>; ISA_IRQS],
+}
+
+pub fn madt() -> Option<Madt> {
+    table(b"APIC").and_then(parse_madt)
+}
+
+fn parse_madt(bytes: &[u8]) -> Option<Madt> {
+    let mut madt = Madt {
+        local_apic_address: u64::from(read_u32(bytes, 36)),
+        pcat_compat: read_u32(bytes, 40) & MADT_PCAT_COMPAT != 0,
+        io_apics: [None; MAX_IO_APICS],
+        overrides: [None; ISA_IRQS],
+    };
+
+    let mut offset = MADT_ENTRIES_OFFSET;
+    while offset + 2 <= bytes.len() {
+        let (kind, length) = (bytes[offset], usize::from(bytes[offset + 1]));
+        let entry = bytes.get(offset..offset + length)?;
+        if length < 2 {
+            return None;
+        }
+        match kind {
+            // 길이가 모자란 항목은 펌웨어의 실수로 보고 건너뜁니다.
+            1 if length >= IO_APIC_ENTRY_LENGTH => {
+                let io_apic = IoApicEntry {
+                    id: entry[2],
+                    address: read_u32(entry, 4),
+                    gsi_base: read_u32(entry, 8),
+                };
+                if let Some(slot) = madt.io_apics.iter_mut().find(|slot| slot.is_none()) {
+                    *slot = Some(io_apic);
+                }
+            }
+            2 if length >= OVERRIDE_ENTRY_LENGTH => {
+                let source = entry[3];
+                if let Some(slot) = madt.overrides.get_mut(usize::from(source)) {
+                    *slot = Some(InterruptOverride {
+                        source,
+                        gsi: read_u32(entry, 4),
+                        flags: u16::from_le_bytes([entry[8], entry[9]]),
+                    });
+                }
+            }
+            5 if length >= LOCAL_APIC_OVERRIDE_ENTRY_LENGTH => madt.local_apic_address = read_u64(entry, 4),
+            _ => {}
+        }
+        offset += length;
+    }
+    Some(madt)
+}
+
+/*
+    HPET 테이블 (서명 "HPET")
+
+    헤더 뒤의 36번 오프셋에 하드웨어 ID(u32)가 있고, 40번 오프셋부터 레지스터 블록의 주소가
+    Generic Address Structure(주소 공간, 비트 폭, 비트 오프셋, 접근 크기, 64비트 주소)로 들어 있습니다.
+*/
+const HPET_ADDRESS_SPACE_OFFSET: usize = 40;
+const HPET_ADDRESS_OFFSET: usize = 44;
+const ADDRESS_SPACE_MEMORY: u8 = 0;
+
+/// HPET 레지스터 블록의 물리 주소
+pub fn hpet_address() -> Option<PhysAddr> {
+    let bytes = table(b"HPET")?;
+    if bytes.get(HPET_ADDRESS_SPACE_OFFSET) != Some(&ADDRESS_SPACE_MEMORY) {
+        return None;
+    }
+    match read_u64(bytes, HPET_ADDRESS_OFFSET) {
+        0 => None,
+        address => PhysAddr::try_new(address).ok(),
+    }
+}
+
+/*
+    AML 인터프리터 없이 _S5 패키지만 찾아냅니다. DSDT에서 해당 부분은 대략 다음과 같이 인코딩되어 있습니다.
+
+        08           NameOp
+        5F 53 35 5F  "_S5_"
+        12           PackageOp
+        0A           PkgLength
+        04           원소 개수
+        0A 05        SLP_TYPa (BytePrefix + 값)
+        0A 05        SLP_TYPb (BytePrefix + 값)
+        00 00        나머지 원소 (사용하지 않음)
+
+    원소 값은 BytePrefix(0x0A)가 붙은 바이트이거나 ZeroOp(0x00) / OneOp(0x01)일 수 있습니다.
+*/
+fn parse_s5(aml: &[u8]) -> Option<(u16, u16)> {
+    const NAME_OP: u8 = 0x08;
+    const PACKAGE_OP: u8 = 0x12;
+    const BYTE_PREFIX: u8 = 0x0A;
+
+    let position = aml.windows(4).position(|window| window == b"_S5_")?;
+    let is_name = match position {
+        0 => false,
+        1 => aml[0] == NAME_OP,
+        // 루트 경로 접두사 '\'가 앞에 붙어 있을 수 있습니다.
+        _ => aml[position - 1] == NAME_OP || (aml[position - 2] == NAME_OP && aml[position - 1] == b'\\'),
+    };
+    if !is_name {
+        return None;
+    }
+
+    let mut rest = aml.get(position + 4..)?.iter().copied();
+    if rest.next()? != PACKAGE_OP {
+        return None;
+    }
+    // PkgLength의 상위 2비트는 뒤따르는 길이 바이트의 수입니다.
+    let pkg_length_lead = rest.next()?;
+    for _ in 0..(pkg_length_lead >> 6) {
+        rest.next()?;
+    }
+    let _element_count = rest.next()?;
+
+    let mut element = || -> Option<u16> {
+        match rest.next()? {
+            BYTE_PREFIX => rest.next().map(u16::from),
+            value => Some(u16::from(value)),
+        }
+    };
+    let slp_typa = element()?;
+    let slp_typb = element()?;
+    Some((slp_typa, slp_typb))
+}
+
+#[test_case]
+fn test_parse_s5_with_byte_prefix() {
+    let aml = [0x10, 0x08, b'_', b'S', b'5', b'_', 0x12, 0x0A, 0x04, 0x0A, 0x05, 0x0A, 0x05, 0x00, 0x00];
+    assert_eq!(parse_s5(&aml), Some((5, 5)));
+}
+
+#[test_case]
+fn test_parse_s5_with_zero_op() {
+    let aml = [0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0x00, 0x00, 0x00, 0x00];
+    assert_eq!(parse_s5(&aml), Some((0, 0)));
+}
+
+#[test_case]
+fn test_short_dsdt_has_no_s5() {
+    assert_eq!(dsdt_s5(&[0; 20]), None);
+    let mut dsdt = [0u8; 36 + 13];
+    dsdt[36..].copy_from_slice(&[0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0x0A, 0x05, 0x00, 0x00]);
+    assert_eq!(dsdt_s5(&dsdt), Some((5, 0)));
+}
+
+#[test_case]
+fn test_root_entries_skip_bad_addresses() {
+    // 헤더보다 짧은 루트 테이블에는 항목이 없습니다.
+    assert_eq!(root_entries(&[0; 20], false).count(), 0);
+
+    let mut xsdt = [0u8; 36 + 16];
+    xsdt[36..44].copy_from_slice(&0xFFF0_0000_0000_1000u64.to_le_bytes());
+    xsdt[44..52].copy_from_slice(&0x7FE_1000u64.to_le_bytes());
+    let mut entries = root_entries(&xsdt, true);
+    assert_eq!(entries.next(), Some(PhysAddr::new(0x7FE_1000)));
+    assert_eq!(entries.next(), None);
+}
+
+#[test_case]
+fn test_checksum() {
+    assert!(checksum_ok(&[0x10, 0xF0]));
+    assert!(!checksum_ok(&[0x10, 0xF1]));
+}
+
+#[test_case]
+fn test_parse_madt() {
+    let mut bytes = [0u8; 44 + 12 + 10];
+    bytes[36..40].copy_from_slice(&0xFEE0_0000u32.to_le_bytes());
+    bytes[40] = 1;
+    // I/O APIC: ID 0, 주소 0xFEC00000, GSI 0부터
+    bytes[44..48].copy_from_slice(&[1, 12, 0, 0]);
+    bytes[48..52].copy_from_slice(&0xFEC0_0000u32.to_le_bytes());
+    // ISA IRQ0 -> GSI2
+    bytes[56..60].copy_from_slice(&[2, 10, 0, 0]);
+    bytes[60..64].copy_from_slice(&2u32.to_le_bytes());
+
+    let madt = parse_madt(&bytes).unwrap();
+    assert_eq!(madt.local_apic_address, 0xFEE0_0000);
+    assert!(madt.pcat_compat);
+    assert_eq!(madt.io_apics[0], Some(IoApicEntry { id: 0, address: 0xFEC0_0000, gsi_base: 0 }));
+    assert_eq!(madt.overrides[0].map(|o| o.gsi), Some(2));
+    assert_eq!(madt.overrides[1], None);
+}
+
+#[test_case]
+fn test_parse_madt_skips_short_entries() {
+    let mut bytes = [0u8; 44 + 4 + 3 + 10];
+    // 길이가 4인 I/O APIC 항목과 길이가 3인 override 항목
+    bytes[44..48].copy_from_slice(&[1, 4, 7, 0]);
+    bytes[48..51].copy_from_slice(&[2, 3, 0]);
+    // 정상적인 override: ISA IRQ9 -> GSI9, active low, level
+    bytes[51..55].copy_from_slice(&[2, 10, 0, 9]);
+    bytes[55..59].copy_from_slice(&9u32.to_le_bytes());
+    bytes[59..61].copy_from_slice(&0x0Fu16.to_le_bytes());
+
+    let madt = parse_madt(&bytes).unwrap();
+    assert_eq!(madt.io_apics, [None; MAX_IO_APICS]);
+    assert_eq!(madt.overrides[0], None);
+    assert_eq!(madt.overrides[9], Some(InterruptOverride { source: 9, gsi: 9, flags: 0x0F }));
+}
@@ -0,0 +1,313 @@
+//! 커널 힙
+//!
+//! 부팅할 때 HEAP_START부터 HEAP_SIZE만큼의 페이지를 매핑하고, 그 영역을 `#[global_allocator]`로 나눠 줍니다.
+//! 그래서 alloc 크레이트의 Box, Vec, String, BTreeMap 등을 쓸 수 있습니다.
+//!
+//! 할당기는 cargo feature로 고릅니다. 아무것도 고르지 않으면 linked list 할당기를 씁니다.
+//! - `bump-allocator`: 가장 빠르지만 모든 할당이 해제되기 전에는 메모리를 거의 되찾지 못합니다.
+//! - (기본) linked list: 어떤 크기든 재사용하고 빈 블록을 합치지만, 할당할 때마다 리스트를 훑습니다.
+//! - `fixed-block-allocator`: 작은 할당을 크기별 리스트에서 바로 꺼내 주고, 큰 할당만 linked list로 넘깁니다.
+//!
+//! 어떤 할당기든 Locked로 감싸서 잠금과 통계(사용량, 최대 사용량, 크기별 할당 수)를 똑같이 얻습니다.
+//! 인터럽트 핸들러에서는 할당하지 않습니다. 핸들러가 할 일은 workqueue로 넘겨서 처리합니다.
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+use crate::memory::{self, MemoryError};
+
+pub mod bump;
+pub mod fixed_block;
+pub mod linked_list;
+pub mod stress;
+
+#[cfg(all(feature = "bump-allocator", feature = "fixed-block-allocator"))]
+compile_error!("choose at most one heap allocator feature");
+
+#[cfg(feature = "bump-allocator")]
+type KernelAllocator = bump::BumpAllocator;
+#[cfg(all(feature = "fixed-block-allocator", not(feature = "bump-allocator")))]
+type KernelAllocator = fixed_block::FixedBlockAllocator;
+#[cfg(not(any(feature = "bump-allocator", feature = "fixed-block-allocator")))]
+type KernelAllocator = linked_list::LinkedListAllocator;
+
+/// 힙이 시작하는 가상 주소. 다른 매핑과 겹치지 않는 하위 절반의 주소입니다.
+pub const HEAP_START: u64 = 0x_4444_4444_0000;
+pub const HEAP_SIZE: usize = 1024 * 1024;
+
+/// 통계에서 할당을 나누는 크기 계열. 고정 크기 블록 할당기의 블록 크기이기도 합니다.
+/// 이보다 큰 할당은 마지막 칸에 모아서 셉니다.
+/// 16바이트부터 시작하는 것은 블록을 받아 오는 linked list 할당기의 최소 블록이 16바이트이기 때문입니다.
+/// 8바이트 계열을 두면 블록마다 절반이 통계에 잡히지 않은 채 버려집니다.
+pub const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+const CLASS_COUNT: usize = SIZE_CLASSES.len() + 1;
+
+// 크기와 정렬을 모두 만족하는 가장 작은 크기 계열. 가장 큰 계열보다 크면 None입니다.
+fn size_class(layout: Layout) -> Option<usize> {
+    let required = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&size| size >= required)
+}
+
+fn align_up(address: usize, align: usize) -> usize {
+    (address + align - 1) & !(align - 1)
+}
+
+/// 힙 영역을 나눠 주는 할당기. 잠금과 통계는 Locked가 맡으므로 여기서는 메모리를 나누는 일만 합니다.
+pub trait HeapAllocator {
+    /// 통계와 오류 메시지에 쓰는 이름
+    const NAME: &'static str;
+
+    /// `[start, start + size)` 영역을 힙으로 씁니다. 이전 상태는 버립니다.
+    ///
+    /// # Safety
+    /// 영역은 매핑되어 있고 다른 곳에서 쓰지 않아야 합니다.
+    unsafe fn init(&mut self, start: usize, size: usize);
+
+    /// 공간이 모자라면 null을 돌려줍니다.
+    fn allocate(&mut self, layout: Layout) -> *mut u8;
+
+    /// # Safety
+    /// `ptr`은 같은 `layout`으로 이 할당기에서 받은 뒤 아직 돌려주지 않은 포인터여야 합니다.
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout);
+
+    /// 다시 나눠 줄 수 있는 공간
+    fn free_space(&self) -> FreeSpace;
+}
+
+/// 할당기가 다시 나눠 줄 수 있는 빈 공간
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FreeSpace {
+    pub bytes: usize,
+    /// 가장 큰 빈 블록의 크기
+    pub largest: usize,
+    pub blocks: usize,
+}
+
+impl FreeSpace {
+    fn add_block(&mut self, size: usize) {
+        self.bytes += size;
+        self.largest = self.largest.max(size);
+        self.blocks += 1;
+    }
+}
+
+/// 크기 계열 하나의 할당 수
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClassCounts {
+    /// 아직 해제되지 않은 할당 수
+    pub live: usize,
+    /// 지금까지의 할당 수
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Counters {
+    size: usize,
+    used: usize,
+    peak: usize,
+    failures: u64,
+    classes: [ClassCounts; CLASS_COUNT],
+}
+
+impl Counters {
+    const fn new() -> Counters {
+        Counters { size: 0, used: 0, peak: 0, failures: 0, classes: [ClassCounts { live: 0, total: 0 }; CLASS_COUNT] }
+    }
+
+    fn class(layout: Layout) -> usize {
+        size_class(layout).unwrap_or(CLASS_COUNT - 1)
+    }
+}
+
+/// 힙 사용 현황
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub size: usize,
+    /// 사용자가 요청한 바이트 수의 합. 할당기가 덧붙인 헤더와 정렬 여백은 빠집니다.
+    pub used: usize,
+    pub peak: usize,
+    pub free: FreeSpace,
+    pub failures: u64,
+    /// SIZE_CLASSES 순서의 크기별 할당 수. 마지막 칸은 가장 큰 계열보다 큰 할당입니다.
+    pub classes: [ClassCounts; CLASS_COUNT],
+}
+
+impl HeapStats {
+    /// 외부 단편화 (%). 빈 공간 중 가장 큰 블록에 들어 있지 않은 비율입니다.
+    pub fn fragmentation(&self) -> usize {
+        match self.free.bytes {
+            0 => 0,
+            free => (free - self.free.largest) * 100 / free,
+        }
+    }
+
+    /// 쓰지도 못하고 다시 나눠 줄 수도 없는 바이트 수 (헤더, 정렬 여백, 되찾지 못한 공간)
+    pub fn overhead(&self) -> usize {
+        self.size.saturating_sub(self.used + self.free.bytes)
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "used {} of {} bytes (peak {}), free {} in {} blocks, largest {}",
+            self.used, self.size, self.peak, self.free.bytes, self.free.blocks, self.free.largest,
+        )?;
+        writeln!(
+            f,
+            "fragmentation {}%, overhead {} bytes, {} failed allocations",
+            self.fragmentation(),
+            self.overhead(),
+            self.failures,
+        )?;
+        writeln!(f, "CLASS       LIVE        TOTAL")?;
+        for (class, counts) in self.classes.iter().enumerate() {
+            if counts.total == 0 {
+                continue;
+            }
+            match SIZE_CLASSES.get(class) {
+                Some(size) => write!(f, "<={:<6}", size)?,
+                None => write!(f, ">{:<7}", SIZE_CLASSES[SIZE_CLASSES.len() - 1])?,
+            }
+            writeln!(f, " {:7} {:12}", counts.live, counts.total)?;
+        }
+        Ok(())
+    }
+}
+
+/// spin::Mutex로 감싼 할당기. GlobalAlloc은 &self만 받으므로 안쪽의 상태를 바꾸려면 잠금이 필요합니다.
+pub struct Locked<A> {
+    inner: Mutex<(A, Counters)>,
+}
+
+impl<A> Locked<A> {
+    pub const fn new(allocator: A) -> Locked<A> {
+        Locked { inner: Mutex::new((allocator, Counters::new())) }
+    }
+}
+
+impl<A: HeapAllocator> Locked<A> {
+    /// 할당기를 초기화하고 통계를 지웁니다.
+    ///
+    /// # Safety
+    /// `HeapAllocator::init`과 같습니다.
+    pub unsafe fn init(&self, start: usize, size: usize) {
+        without_interrupts(|| {
+            let (allocator, counters) = &mut *self.inner.lock();
+            allocator.init(start, size);
+            *counters = Counters::new();
+            counters.size = size;
+        })
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        without_interrupts(|| {
+            let (allocator, counters) = &*self.inner.lock();
+            HeapStats {
+                size: counters.size,
+                used: counters.used,
+                peak: counters.peak,
+                free: allocator.free_space(),
+                failures: counters.failures,
+                classes: counters.classes,
+            }
+        })
+    }
+}
+
+unsafe impl<A: HeapAllocator> GlobalAlloc for Locked<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| {
+            let (allocator, counters) = &mut *self.inner.lock();
+            let ptr = allocator.allocate(layout);
+            if ptr.is_null() {
+                counters.failures += 1;
+            } else {
+                counters.used += layout.size();
+                counters.peak = counters.peak.max(counters.used);
+                let class = &mut counters.classes[Counters::class(layout)];
+                class.live += 1;
+                class.total += 1;
+            }
+            ptr
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| {
+            let (allocator, counters) = &mut *self.inner.lock();
+            allocator.deallocate(ptr, layout);
+            counters.used -= layout.size();
+            counters.classes[Counters::class(layout)].live -= 1;
+        })
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Locked<KernelAllocator> = Locked::new(KernelAllocator::new());
+
+/// 힙 영역의 페이지를 매핑하고 할당기를 초기화합니다. `memory::init` 다음에 한 번 호출합니다.
+pub fn init_heap() -> Result<(), MemoryError> {
+    let start = Page::containing_address(VirtAddr::new(HEAP_START));
+    let end = Page::containing_address(VirtAddr::new(HEAP_START + HEAP_SIZE as u64 - 1));
+    for page in Page::range_inclusive(start, end) {
+        memory::map(page, PageTableFlags::WRITABLE)?;
+    }
+    unsafe { ALLOCATOR.init(HEAP_START as usize, HEAP_SIZE) };
+    Ok(())
+}
+
+/// 커널 힙의 통계
+pub fn stats() -> HeapStats {
+    ALLOCATOR.stats()
+}
+
+/// feature로 고른 할당기의 이름
+pub fn allocator_name() -> &'static str {
+    KernelAllocator::NAME
+}
+
+/*
+    할당 실패
+
+    alloc 크레이트는 할당이 실패하면 이 함수를 부릅니다. 어떤 크기를 요청했는지와 힙이 얼마나 조각났는지를 남깁니다.
+    (GlobalAlloc은 null을 돌려주기 전에 잠금을 풀기 때문에 stats()가 잠금을 기다리며 멈추지 않습니다.)
+*/
+pub fn alloc_error(layout: Layout) -> ! {
+    panic!(
+        "allocation error: {} bytes (align {}) from the {} allocator, heap: {}",
+        layout.size(),
+        layout.align(),
+        allocator_name(),
+        stats(),
+    );
+}
+
+#[test_case]
+fn test_stats_track_live_allocations() {
+    let mut area = [0u64; 512];
+    let heap = Locked::new(linked_list::LinkedListAllocator::new());
+    unsafe { heap.init(area.as_mut_ptr() as usize, 4096) };
+
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let large = Layout::from_size_align(3000, 8).unwrap();
+    let (a, b) = unsafe { (heap.alloc(small), heap.alloc(large)) };
+    assert!(unsafe { heap.alloc(large) }.is_null());
+
+    let stats = heap.stats();
+    assert_eq!((stats.used, stats.peak, stats.failures), (3024, 3024, 1));
+    let class = size_class(small).unwrap();
+    assert_eq!(stats.classes[class], ClassCounts { live: 1, total: 1 });
+    assert_eq!(stats.classes[CLASS_COUNT - 1], ClassCounts { live: 1, total: 1 });
+
+    unsafe {
+        heap.dealloc(b, large);
+        heap.dealloc(a, small);
+    }
+    let stats = heap.stats();
+    assert_eq!((stats.used, stats.peak, stats.fragmentation()), (0, 3024, 0));
+    assert_eq!(stats.classes[class].live, 0);
+}
@@ -0,0 +1,135 @@
+//! 고정 크기 블록 할당기
+//!
+//! 요청을 SIZE_CLASSES 중 맞는 크기로 올려서 크기별 빈 블록 리스트에서 꺼내 줍니다. 리스트가 비어 있을 때와
+//! 가장 큰 크기보다 큰 요청만 linked list 할당기로 넘기므로, 작은 할당이 많은 경우 리스트를 훑지 않고 바로 끝납니다.
+//! 돌려받은 블록은 같은 크기의 리스트에 남겨 두고 합치지 않으므로, 크기를 올린 만큼과 남겨 둔 블록만큼 메모리를 더 씁니다.
+use core::alloc::Layout;
+use core::ptr::{self, NonNull};
+use super::linked_list::LinkedListAllocator;
+use super::{size_class, FreeSpace, HeapAllocator, SIZE_CLASSES};
+
+// 빈 블록에 쓰는 리스트 노드. 가장 작은 블록(16바이트)에도 들어갑니다.
+struct FreeBlock {
+    next: Option<NonNull<FreeBlock>>,
+}
+
+const CLASSES: usize = SIZE_CLASSES.len();
+
+pub struct FixedBlockAllocator {
+    heads: [Option<NonNull<FreeBlock>>; CLASSES],
+    cached: [usize; CLASSES],
+    fallback: LinkedListAllocator,
+}
+
+// 빈 블록은 힙 안에만 있고 Mutex로만 접근하므로 다른 CPU 문맥으로 옮겨도 됩니다.
+unsafe impl Send for FixedBlockAllocator {}
+
+impl FixedBlockAllocator {
+    pub const fn new() -> FixedBlockAllocator {
+        FixedBlockAllocator { heads: [None; CLASSES], cached: [0; CLASSES], fallback: LinkedListAllocator::new() }
+    }
+}
+
+impl HeapAllocator for FixedBlockAllocator {
+    const NAME: &'static str = "fixed block";
+
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        self.heads = [None; CLASSES];
+        self.cached = [0; CLASSES];
+        self.fallback.init(start, size);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let class = match size_class(layout) {
+            Some(class) => class,
+            None => return self.fallback.allocate(layout),
+        };
+        match self.heads[class] {
+            Some(block) => {
+                self.heads[class] = unsafe { block.as_ref().next };
+                self.cached[class] -= 1;
+                block.as_ptr() as *mut u8
+            }
+            // 블록 크기로 정렬해 두면 크기보다 작은 정렬 요구는 모두 만족합니다.
+            None => {
+                let size = SIZE_CLASSES[class];
+                match Layout::from_size_align(size, size) {
+                    Ok(layout) => self.fallback.allocate(layout),
+                    Err(_) => ptr::null_mut(),
+                }
+            }
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let class = match size_class(layout) {
+            Some(class) => class,
+            None => return self.fallback.deallocate(ptr, layout),
+        };
+        let block = ptr as *mut FreeBlock;
+        block.write(FreeBlock { next: self.heads[class] });
+        self.heads[class] = NonNull::new(block);
+        self.cached[class] += 1;
+    }
+
+    // 리스트에 남겨 둔 블록도 같은 크기의 요청에는 다시 쓸 수 있으므로 빈 공간으로 셉니다.
+    fn free_space(&self) -> FreeSpace {
+        let mut space = self.fallback.free_space();
+        for (&size, &count) in SIZE_CLASSES.iter().zip(self.cached.iter()) {
+            for _ in 0..count {
+                space.add_block(size);
+            }
+        }
+        space
+    }
+}
+
+#[test_case]
+fn test_blocks_are_reused_by_size_class() {
+    #[repr(C, align(4096))]
+    struct Area([u8; 8192]);
+    let mut area = Area([0; 8192]);
+    let mut heap = FixedBlockAllocator::new();
+    unsafe { heap.init(area.0.as_mut_ptr() as usize, 8192) };
+
+    let small = Layout::from_size_align(20, 4).unwrap();
+    let a = heap.allocate(small);
+    assert_eq!(a as usize % 32, 0);
+    unsafe { heap.deallocate(a, small) };
+    // 같은 크기 계열(32바이트)의 요청은 방금 돌려준 블록을 받습니다.
+    assert_eq!(heap.allocate(Layout::from_size_align(32, 8).unwrap()), a);
+
+    // 돌려준 작은 블록은 리스트에 남아 빈 공간으로 잡힙니다.
+    let before = heap.free_space();
+    unsafe { heap.deallocate(a, small) };
+    let after = heap.free_space();
+    assert_eq!((after.bytes, after.blocks), (before.bytes + 32, before.blocks + 1));
+    assert_eq!(heap.fallback.free_space(), before);
+
+    // 가장 큰 계열보다 큰 블록은 리스트에 남지 않고 linked list 할당기로 돌아갑니다.
+    let large = Layout::from_size_align(4000, 8).unwrap();
+    let fallback = heap.fallback.free_space();
+    let b = heap.allocate(large);
+    assert!(!b.is_null());
+    unsafe { heap.deallocate(b, large) };
+    assert_eq!(heap.fallback.free_space(), fallback);
+    assert_eq!(heap.cached.iter().sum::<usize>(), 1);
+}
+
+#[test_case]
+fn test_smallest_class_wastes_nothing() {
+    #[repr(C, align(4096))]
+    struct Area([u8; 4096]);
+    let mut area = Area([0; 4096]);
+    let mut heap = FixedBlockAllocator::new();
+    unsafe { heap.init(area.0.as_mut_ptr() as usize, 4096) };
+
+    // 8바이트 요청도 linked list 할당기의 최소 블록과 같은 16바이트 블록을 받으므로, 모든 바이트가 블록이나 빈 공간으로 잡힙니다.
+    let layout = Layout::from_size_align(8, 8).unwrap();
+    let blocks = [(); 4].map(|_| heap.allocate(layout));
+    assert_eq!(heap.free_space().bytes, 4096 - 4 * SIZE_CLASSES[0]);
+    for block in blocks {
+        unsafe { heap.deallocate(block, layout) };
+    }
+    assert_eq!(heap.free_space().bytes, 4096);
+}
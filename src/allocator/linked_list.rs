@@ -0,0 +1,174 @@
+//! 빈 블록을 주소 순서로 연결한 리스트(free list) 할당기
+//!
+//! 할당할 때는 맞는 크기의 첫 블록을 잘라 쓰고(first fit), 해제할 때는 주소 순서에 맞게 끼워 넣으면서
+//! 앞뒤의 빈 블록과 합쳐서 조각이 쌓이지 않게 합니다. 어떤 크기든 재사용할 수 있지만 할당할 때마다 리스트를 훑습니다.
+use core::alloc::Layout;
+use core::mem;
+use core::ptr::{self, NonNull};
+use super::{align_up, FreeSpace, HeapAllocator};
+
+// 빈 블록의 맨 앞에 놓이는 헤더. 블록의 크기는 헤더를 포함합니다.
+struct FreeBlock {
+    size: usize,
+    next: Option<NonNull<FreeBlock>>,
+}
+
+const MIN_BLOCK: usize = mem::size_of::<FreeBlock>();
+const BLOCK_ALIGN: usize = mem::align_of::<FreeBlock>();
+
+pub struct LinkedListAllocator {
+    head: Option<NonNull<FreeBlock>>,
+}
+
+// 빈 블록은 힙 안에만 있고 Mutex로만 접근하므로 다른 CPU 문맥으로 옮겨도 됩니다.
+unsafe impl Send for LinkedListAllocator {}
+
+impl LinkedListAllocator {
+    pub const fn new() -> LinkedListAllocator {
+        LinkedListAllocator { head: None }
+    }
+
+    // 헤더를 넣을 수 있도록 크기와 정렬을 올립니다.
+    fn block_layout(layout: Layout) -> (usize, usize) {
+        let size = align_up(layout.size().max(MIN_BLOCK), BLOCK_ALIGN);
+        (size, layout.align().max(BLOCK_ALIGN))
+    }
+
+    // 주소 순서를 지키며 빈 블록을 끼워 넣고, 바로 앞이나 뒤의 빈 블록과 맞닿아 있으면 합칩니다.
+    unsafe fn free(&mut self, start: usize, size: usize) {
+        debug_assert!(start % BLOCK_ALIGN == 0 && size >= MIN_BLOCK);
+
+        let mut previous: Option<NonNull<FreeBlock>> = None;
+        let mut current = self.head;
+        while let Some(block) = current {
+            if block.as_ptr() as usize > start {
+                break;
+            }
+            previous = current;
+            current = block.as_ref().next;
+        }
+
+        let new = start as *mut FreeBlock;
+        new.write(FreeBlock { size, next: current });
+        let mut new = NonNull::new_unchecked(new);
+
+        if let Some(next) = current {
+            if start + size == next.as_ptr() as usize {
+                let next = next.as_ref();
+                new.as_mut().size += next.size;
+                new.as_mut().next = next.next;
+            }
+        }
+        match previous {
+            Some(mut previous) => {
+                let previous_start = previous.as_ptr() as usize;
+                if previous_start + previous.as_ref().size == start {
+                    previous.as_mut().size += new.as_ref().size;
+                    previous.as_mut().next = new.as_ref().next;
+                } else {
+                    previous.as_mut().next = Some(new);
+                }
+            }
+            None => self.head = Some(new),
+        }
+    }
+}
+
+impl HeapAllocator for LinkedListAllocator {
+    const NAME: &'static str = "linked list";
+
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        let aligned = align_up(start, BLOCK_ALIGN);
+        let size = (start + size).saturating_sub(aligned) & !(BLOCK_ALIGN - 1);
+        self.head = None;
+        self.free(aligned, size);
+    }
+
+    /*
+        블록의 앞이나 뒤에 남는 부분이 헤더보다 작으면 다시 free list에 넣을 수 없으므로 그런 블록은 쓰지 않습니다.
+        정렬 때문에 앞에 남는 부분은 빈 블록으로 돌려놓고, 뒤에 남는 부분도 마찬가지로 돌려놓습니다.
+    */
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::block_layout(layout);
+        let mut previous: Option<NonNull<FreeBlock>> = None;
+        let mut current = self.head;
+
+        while let Some(mut block) = current {
+            let (start, end, next) = unsafe {
+                let block = block.as_mut();
+                let start = block as *mut FreeBlock as usize;
+                (start, start + block.size, block.next)
+            };
+            let mut alloc_start = align_up(start, align);
+            if alloc_start != start && alloc_start - start < MIN_BLOCK {
+                alloc_start = align_up(start + MIN_BLOCK, align);
+            }
+            let alloc_end = alloc_start.saturating_add(size);
+            let tail = end.saturating_sub(alloc_end);
+            if alloc_end <= end && (tail == 0 || tail >= MIN_BLOCK) {
+                // 블록을 리스트에서 떼어낸 뒤 남는 부분을 다시 넣습니다.
+                match previous {
+                    Some(mut previous) => unsafe { previous.as_mut().next = next },
+                    None => self.head = next,
+                }
+                unsafe {
+                    if alloc_start != start {
+                        self.free(start, alloc_start - start);
+                    }
+                    if tail != 0 {
+                        self.free(alloc_end, tail);
+                    }
+                }
+                return alloc_start as *mut u8;
+            }
+            previous = current;
+            current = next;
+        }
+        ptr::null_mut()
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::block_layout(layout);
+        self.free(ptr as usize, size);
+    }
+
+    fn free_space(&self) -> FreeSpace {
+        let mut space = FreeSpace::default();
+        let mut current = self.head;
+        while let Some(block) = current {
+            let block = unsafe { block.as_ref() };
+            space.add_block(block.size);
+            current = block.next;
+        }
+        space
+    }
+}
+
+#[test_case]
+fn test_free_blocks_are_merged() {
+    #[repr(C, align(16))]
+    struct Area([u8; 1024]);
+    let mut area = Area([0; 1024]);
+    let mut heap = LinkedListAllocator::new();
+    unsafe { heap.init(area.0.as_mut_ptr() as usize, 1024) };
+
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let a = heap.allocate(layout);
+    let b = heap.allocate(layout);
+    let c = heap.allocate(layout);
+    assert!(!a.is_null() && !b.is_null() && !c.is_null());
+    assert_eq!(heap.free_space().bytes, 1024 - 3 * 104);
+
+    // 가운데 블록을 먼저 돌려주면 조각이 생기고, 나머지를 돌려주면 다시 하나로 합쳐집니다.
+    unsafe { heap.deallocate(b, layout) };
+    assert_eq!(heap.free_space().blocks, 2);
+    unsafe {
+        heap.deallocate(a, layout);
+        heap.deallocate(c, layout);
+    }
+    assert_eq!(heap.free_space(), FreeSpace { bytes: 1024, largest: 1024, blocks: 1 });
+
+    let aligned = heap.allocate(Layout::from_size_align(64, 256).unwrap());
+    assert_eq!(aligned as usize % 256, 0);
+    assert!(heap.allocate(Layout::from_size_align(2048, 8).unwrap()).is_null());
+}
@@ -0,0 +1,226 @@
+//! 할당기 스트레스 테스트
+//!
+//! 세 가지 작업 부하를 같은 순서로 돌려서 할당기끼리 비교할 수 있는 숫자를 얻습니다.
+//! - LIFO: 작은 블록을 쌓았다가 역순으로 해제합니다. (함수 안의 임시 버퍼)
+//! - random: 크기와 정렬이 제각각인 할당과 해제를 섞습니다. (오래 실행되는 셸)
+//! - grow: 버퍼를 두 배씩 키우며 옮기고, 사이사이에 오래 사는 작은 블록을 남깁니다. (Vec이 자라는 경우)
+//! 모든 블록은 받자마자 패턴으로 채우고 해제하기 전에 확인하므로, 블록이 겹치면 바로 panic합니다.
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::ptr;
+use crate::tsc;
+use super::{HeapAllocator, Locked};
+
+const SLOTS: usize = 64;
+const LIFO_ROUNDS: usize = 200;
+const RANDOM_STEPS: usize = 5000;
+const GROW_ROUNDS: usize = 8;
+const GROW_LIMIT: usize = 16 * 1024;
+
+/// 스트레스 테스트 한 번의 결과
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StressReport {
+    pub name: &'static str,
+    pub allocations: u64,
+    pub failures: u64,
+    /// 동시에 살아 있던 요청 바이트 수의 최댓값
+    pub peak: usize,
+    /// random 부하가 끝났을 때(블록을 모두 해제하기 전)의 외부 단편화 (%)
+    pub fragmentation: usize,
+    pub cycles: u64,
+}
+
+impl fmt::Display for StressReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:12} {:8} {:8} {:8} {:5}% {:12}",
+            self.name, self.allocations, self.failures, self.peak, self.fragmentation, self.cycles,
+        )
+    }
+}
+
+/// `StressReport`를 표로 출력할 때 쓰는 머리글
+pub const HEADER: &str = "ALLOCATOR      ALLOCS FAILURES     PEAK  FRAG%       CYCLES";
+
+// 테스트마다 같은 순서가 나오도록 씨앗이 고정된 xorshift
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, limit: usize) -> usize {
+        (self.next() % limit as u64) as usize
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Block {
+    ptr: *mut u8,
+    layout: Layout,
+    tag: u8,
+}
+
+struct Tester<'a, A: HeapAllocator> {
+    heap: &'a Locked<A>,
+    next_tag: u8,
+}
+
+impl<A: HeapAllocator> Tester<'_, A> {
+    fn allocate(&mut self, size: usize, align: usize) -> Option<Block> {
+        let layout = Layout::from_size_align(size, align).expect("invalid stress layout");
+        let ptr = unsafe { self.heap.alloc(layout) };
+        if ptr.is_null() {
+            return None;
+        }
+        assert_eq!(ptr as usize % align, 0, "{} allocator returned a misaligned block", A::NAME);
+        self.next_tag = self.next_tag.wrapping_add(1);
+        let block = Block { ptr, layout, tag: self.next_tag };
+        unsafe { ptr::write_bytes(ptr, block.tag, size) };
+        Some(block)
+    }
+
+    fn free(&mut self, block: Block) {
+        let bytes = unsafe { core::slice::from_raw_parts(block.ptr, block.layout.size()) };
+        assert!(
+            bytes.iter().all(|&byte| byte == block.tag),
+            "{} allocator handed out overlapping blocks",
+            A::NAME,
+        );
+        unsafe { self.heap.dealloc(block.ptr, block.layout) };
+    }
+
+    fn free_all(&mut self, slots: &mut [Option<Block>]) {
+        for slot in slots.iter_mut() {
+            if let Some(block) = slot.take() {
+                self.free(block);
+            }
+        }
+    }
+
+    fn lifo(&mut self) {
+        let mut slots = [None; SLOTS];
+        for round in 0..LIFO_ROUNDS {
+            let depth = 1 + round % SLOTS;
+            for (index, slot) in slots.iter_mut().take(depth).enumerate() {
+                *slot = self.allocate(8 << (index % 7), 8);
+            }
+            for slot in slots.iter_mut().take(depth).rev() {
+                if let Some(block) = slot.take() {
+                    self.free(block);
+                }
+            }
+        }
+    }
+
+    fn random(&mut self) -> usize {
+        let mut random = Random(0x2545_F491_4F6C_DD1D);
+        let mut slots = [None; SLOTS];
+        for _ in 0..RANDOM_STEPS {
+            let slot = &mut slots[random.below(SLOTS)];
+            match slot.take() {
+                Some(block) => self.free(block),
+                None => {
+                    // 작은 할당이 대부분이고 가끔 큰 할당이 섞입니다.
+                    let size = match random.below(8) {
+                        0 => 1 + random.below(4096),
+                        1 | 2 => 1 + random.below(512),
+                        _ => 1 + random.below(64),
+                    };
+                    let align = [8, 8, 16, 64][random.below(4)];
+                    *slot = self.allocate(size, align);
+                }
+            }
+        }
+        let fragmentation = self.heap.stats().fragmentation();
+        self.free_all(&mut slots);
+        fragmentation
+    }
+
+    fn grow(&mut self) {
+        let mut kept = [None; SLOTS];
+        for round in 0..GROW_ROUNDS {
+            let mut buffer = self.allocate(16, 8);
+            let mut size = 16;
+            let mut index = round * (SLOTS / GROW_ROUNDS);
+            while size < GROW_LIMIT {
+                size *= 2;
+                let bigger = self.allocate(size, 8);
+                if let Some(old) = buffer {
+                    self.free(old);
+                }
+                buffer = bigger;
+                // 옮긴 뒤 오래 사는 작은 블록을 남겨서 빈 자리를 조각냅니다.
+                if index < SLOTS {
+                    kept[index] = self.allocate(24, 8);
+                    index += 1;
+                }
+            }
+            if let Some(block) = buffer {
+                self.free(block);
+            }
+        }
+        self.free_all(&mut kept);
+    }
+}
+
+/// 초기화된 `heap`에 세 가지 부하를 차례로 돌립니다. 끝나면 모든 블록이 해제되어 있습니다.
+pub fn run<A: HeapAllocator>(heap: &Locked<A>) -> StressReport {
+    let start = tsc::read();
+    let mut tester = Tester { heap, next_tag: 0 };
+    tester.lifo();
+    let fragmentation = tester.random();
+    tester.grow();
+    let cycles = tsc::read() - start;
+
+    let stats = heap.stats();
+    assert_eq!(stats.used, 0, "{} allocator leaked during the stress test", A::NAME);
+    StressReport {
+        name: A::NAME,
+        allocations: stats.classes.iter().map(|class| class.total).sum(),
+        failures: stats.failures,
+        peak: stats.peak,
+        fragmentation,
+        cycles,
+    }
+}
+
+#[test_case]
+fn test_stress_all_allocators() {
+    use super::bump::BumpAllocator;
+    use super::fixed_block::FixedBlockAllocator;
+    use super::linked_list::LinkedListAllocator;
+
+    const AREA_SIZE: usize = 512 * 1024;
+    #[repr(C, align(4096))]
+    struct Area([u8; AREA_SIZE]);
+    static mut AREA: Area = Area([0; AREA_SIZE]);
+    let start = unsafe { AREA.0.as_mut_ptr() as usize };
+
+    // 모든 할당기가 같은 영역을 씁니다. 각 테스트가 끝나면 모든 블록이 해제되어 있으므로 다시 초기화해도 됩니다.
+    let bump = Locked::new(BumpAllocator::new());
+    let linked_list = Locked::new(LinkedListAllocator::new());
+    let fixed_block = Locked::new(FixedBlockAllocator::new());
+    let reports = unsafe {
+        bump.init(start, AREA_SIZE);
+        let bump = run(&bump);
+        linked_list.init(start, AREA_SIZE);
+        let linked_list = run(&linked_list);
+        fixed_block.init(start, AREA_SIZE);
+        [bump, linked_list, run(&fixed_block)]
+    };
+
+    crate::serial_println!();
+    crate::serial_println!("{}", HEADER);
+    for report in reports.iter() {
+        crate::serial_println!("{}", report);
+    }
+    // 메모리를 재사용하는 할당기는 이 정도 부하에서 실패하지 않아야 합니다.
+    assert_eq!(reports[1].failures, 0);
+    assert_eq!(reports[2].failures, 0);
+}
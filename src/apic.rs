@@ -0,0 +1,377 @@
+//! Local APIC와 I/O APIC
+//!
+//! APIC 구조에서는 CPU마다 하나씩 있는 Local APIC가 인터럽트를 받아 CPU에 전달하고,
+//! I/O APIC가 장치의 인터럽트 라인(GSI)을 원하는 CPU의 원하는 벡터로 보내줍니다.
+//! 두 장치 모두 레지스터가 메모리에 매핑(MMIO)되어 있고, 주소는 ACPI의 MADT로 알아냅니다.
+use core::ptr;
+use spin::{Mutex, Once};
+use x86_64::registers::model_specific::Msr;
+use x86_64::VirtAddr;
+use crate::acpi::{self, IoApicEntry, Madt, MAX_IO_APICS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicError {
+    /// CPUID가 APIC를 지원하지 않는다고 보고했습니다.
+    Unsupported,
+    /// ACPI 테이블에서 MADT를 찾지 못했습니다.
+    MadtNotFound,
+    /// MADT에 I/O APIC 항목이 없습니다.
+    IoApicNotFound,
+}
+
+// IDT의 마지막 벡터를 spurious interrupt에 사용합니다. 하위 4비트가 1111이어야 하는 오래된 CPU와도 호환됩니다.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/*
+    Local APIC 레지스터 (기본 주소로부터의 오프셋)
+*/
+mod reg {
+    pub const ID: usize = 0x20;
+    pub const TASK_PRIORITY: usize = 0x80;
+    pub const EOI: usize = 0xB0;
+    pub const SPURIOUS: usize = 0xF0;
+    pub const IN_SERVICE: usize = 0x100;
+    pub const ERROR_STATUS: usize = 0x280;
+    pub const LVT_TIMER: usize = 0x320;
+    pub const LVT_LINT0: usize = 0x350;
+    pub const LVT_LINT1: usize = 0x360;
+    pub const LVT_ERROR: usize = 0x370;
+}
+
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+
+pub struct LocalApic {
+    base: VirtAddr,
+}
+
+impl LocalApic {
+    unsafe fn read(&self, register: usize) -> u32 {
+        ptr::read_volatile((self.base + register).as_ptr())
+    }
+
+    unsafe fn write(&self, register: usize, value: u32) {
+        ptr::write_volatile((self.base + register).as_mut_ptr(), value);
+    }
+
+    unsafe fn enable(&self) {
+        let mut apic_base = Msr::new(IA32_APIC_BASE);
+        let value = apic_base.read();
+        apic_base.write(value | APIC_BASE_ENABLE);
+
+        // 아직 사용하지 않는 로컬 인터럽트 소스는 모두 마스크합니다.
+        for &lvt in [reg::LVT_TIMER, reg::LVT_LINT0, reg::LVT_LINT1, reg::LVT_ERROR].iter() {
+            self.write(lvt, LVT_MASKED);
+        }
+        // ESR은 읽기 전에 한 번 써야 값이 갱신됩니다.
+        self.write(reg::ERROR_STATUS, 0);
+        self.write(reg::ERROR_STATUS, 0);
+
+        self.write(reg::TASK_PRIORITY, 0);
+        self.write(reg::SPURIOUS, SPURIOUS_APIC_ENABLE | u32::from(SPURIOUS_VECTOR));
+        self.end_of_interrupt();
+    }
+
+    pub fn id(&self) -> u8 {
+        (unsafe { self.read(reg::ID) } >> 24) as u8
+    }
+
+    pub fn end_of_interrupt(&self) {
+        unsafe { self.write(reg::EOI, 0) };
+    }
+
+    /// 벡터가 ISR(In-Service Register)에 표시되어 있는지, 즉 EOI를 기다리는 중인지 확인합니다.
+    /// ISR은 32비트 레지스터 8개(0x10 간격)에 256개 벡터가 한 비트씩 나뉘어 있습니다.
+    pub fn in_service(&self, vector: u8) -> bool {
+        let register = reg::IN_SERVICE + usize::from(vector / 32) * 0x10;
+        unsafe { self.read(register) & (1 << (vector % 32)) != 0 }
+    }
+}
+
+/*
+    I/O APIC 레지스터는 간접적으로 접근합니다.
+    IOREGSEL(오프셋 0x00)에 레지스터 번호를 쓰고 IOWIN(오프셋 0x10)으로 값을 읽거나 씁니다.
+    리다이렉션 테이블의 n번째 항목은 64비트이며 0x10 + 2n(하위), 0x11 + 2n(상위) 레지스터에 나뉘어 있습니다.
+*/
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION_TABLE: u32 = 0x10;
+
+const REDIRECTION_DELIVERY_NMI: u64 = 0b100 << 8;
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+pub struct IoApic {
+    base: VirtAddr,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl IoApic {
+    unsafe fn new(base: VirtAddr, gsi_base: u32) -> IoApic {
+        let mut io_apic = IoApic { base, gsi_base, entries: 0 };
+        io_apic.entries = ((io_apic.read(IOAPIC_VERSION) >> 16) & 0xFF) + 1;
+        io_apic
+    }
+
+    unsafe fn read(&self, register: u32) -> u32 {
+        ptr::write_volatile((self.base + IOREGSEL).as_mut_ptr(), register);
+        ptr::read_volatile((self.base + IOWIN).as_ptr())
+    }
+
+    unsafe fn write(&self, register: u32, value: u32) {
+        ptr::write_volatile((self.base + IOREGSEL).as_mut_ptr(), register);
+        ptr::write_volatile((self.base + IOWIN).as_mut_ptr(), value);
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+
+    fn read_entry(&self, gsi: u32) -> u64 {
+        let register = IOAPIC_REDIRECTION_TABLE + 2 * (gsi - self.gsi_base);
+        unsafe { u64::from(self.read(register)) | u64::from(self.read(register + 1)) << 32 }
+    }
+
+    fn write_entry(&self, gsi: u32, entry: u64) {
+        let register = IOAPIC_REDIRECTION_TABLE + 2 * (gsi - self.gsi_base);
+        unsafe {
+            // 상위 절반(목적지)을 먼저 써서 중간 상태의 항목이 잘못된 CPU로 가지 않게 합니다.
+            self.write(register + 1, (entry >> 32) as u32);
+            self.write(register, entry as u32);
+        }
+    }
+}
+
+// ISA IRQ가 연결된 GSI와 리다이렉션 항목 설정
+#[derive(Debug, Clone, Copy)]
+struct IsaRoute {
+    gsi: u32,
+    active_low: bool,
+    level_triggered: bool,
+}
+
+struct Apic {
+    local: LocalApic,
+    io_apics: Mutex<[Option<IoApic>; MAX_IO_APICS]>,
+    isa_routes: [Option<IsaRoute>; acpi::ISA_IRQS],
+}
+
+static APIC: Once<Apic> = Once::new();
+
+fn apic_supported() -> bool {
+    const CPUID_FEATURE_APIC: u32 = 1 << 9;
+    let features = unsafe { core::arch::x86_64::__cpuid(1) };
+    features.edx & CPUID_FEATURE_APIC != 0
+}
+
+/*
+    ISA IRQ는 기본적으로 같은 번호의 GSI에 엣지 트리거, active high로 연결됩니다.
+    MADT의 override 항목이 있으면 그 값을 따릅니다. (QEMU에서는 IRQ0(PIT)이 GSI2로 연결됩니다.)
+    다른 IRQ의 override가 차지한 GSI에는 같은 번호의 IRQ를 연결하지 않습니다. 그러지 않으면 나중에 쓴 항목이
+    override의 벡터를 덮어씁니다. IRQ2는 8259의 cascade 라인이라 APIC에서는 쓰지 않습니다.
+*/
+const CASCADE_IRQ: usize = 2;
+
+fn isa_routes(madt: &Madt) -> [Option<IsaRoute>; acpi::ISA_IRQS] {
+    const POLARITY_ACTIVE_LOW: u16 = 0b11;
+    const TRIGGER_LEVEL: u16 = 0b11 << 2;
+
+    let claimed = |gsi: u32| {
+        madt.overrides.iter().flatten().any(|over| over.gsi == gsi && u32::from(over.source) != gsi)
+    };
+    let mut routes = [None; acpi::ISA_IRQS];
+    for (irq, route) in routes.iter_mut().enumerate() {
+        if irq == CASCADE_IRQ {
+            continue;
+        }
+        *route = match madt.overrides[irq] {
+            Some(over) => Some(IsaRoute {
+                gsi: over.gsi,
+                active_low: over.flags & POLARITY_ACTIVE_LOW == POLARITY_ACTIVE_LOW,
+                level_triggered: over.flags & TRIGGER_LEVEL == TRIGGER_LEVEL,
+            }),
+            None if claimed(irq as u32) => None,
+            None => Some(IsaRoute { gsi: irq as u32, active_low: false, level_triggered: false }),
+        };
+    }
+    routes
+}
+
+/// Local APIC를 켜고 I/O APIC의 모든 항목을 마스크된 상태로 초기화합니다.
+/// ISA IRQ n은 PIC를 사용할 때와 같은 벡터 `first_vector + n`으로 연결됩니다.
+///
+/// # Safety
+/// 물리 메모리 전체가 `physical_memory_offset`부터 매핑되어 있어야 하고, 인터럽트가 꺼진 상태에서 호출해야 합니다.
+pub unsafe fn init(physical_memory_offset: VirtAddr, first_vector: u8) -> Result<(), ApicError> {
+    if !apic_supported() {
+        return Err(ApicError::Unsupported);
+    }
+    let madt = acpi::madt().ok_or(ApicError::MadtNotFound)?;
+    if madt.io_apics.iter().all(Option::is_none) {
+        return Err(ApicError::IoApicNotFound);
+    }
+
+    let local = LocalApic {
+        base: physical_memory_offset + madt.local_apic_address,
+    };
+    local.enable();
+
+    let mut io_apics: [Option<IoApic>; MAX_IO_APICS] = [(); MAX_IO_APICS].map(|_| None);
+    for (slot, entry) in io_apics.iter_mut().zip(madt.io_apics.iter()) {
+        if let Some(IoApicEntry { address, gsi_base, .. }) = *entry {
+            let io_apic = IoApic::new(physical_memory_offset + u64::from(address), gsi_base);
+            for gsi in gsi_base..gsi_base + io_apic.entries {
+                io_apic.write_entry(gsi, REDIRECTION_MASKED);
+            }
+            *slot = Some(io_apic);
+        }
+    }
+
+    let routes = isa_routes(&madt);
+    let destination = u64::from(local.id()) << 56;
+    for (irq, route) in routes.iter().enumerate() {
+        let route = match route {
+            Some(route) => route,
+            None => continue,
+        };
+        let mut entry = (u64::from(first_vector) + irq as u64) | destination | REDIRECTION_MASKED;
+        if route.active_low {
+            entry |= REDIRECTION_ACTIVE_LOW;
+        }
+        if route.level_triggered {
+            entry |= REDIRECTION_LEVEL_TRIGGERED;
+        }
+        if let Some(io_apic) = io_apics.iter().flatten().find(|io_apic| io_apic.handles(route.gsi)) {
+            io_apic.write_entry(route.gsi, entry);
+        }
+    }
+
+    APIC.call_once(|| Apic {
+        local,
+        io_apics: Mutex::new(io_apics),
+        isa_routes: routes,
+    });
+    Ok(())
+}
+
+pub fn is_active() -> bool {
+    APIC.r#try().is_some()
+}
+
+pub fn local_apic() -> Option<&'static LocalApic> {
+    APIC.r#try().map(|apic| &apic.local)
+}
+
+pub fn end_of_interrupt() {
+    if let Some(apic) = APIC.r#try() {
+        apic.local.end_of_interrupt();
+    }
+}
+
+fn set_isa_irq_masked(irq: u8, masked: bool) {
+    let apic = match APIC.r#try() {
+        Some(apic) => apic,
+        None => return,
+    };
+    let route = match apic.isa_routes.get(usize::from(irq)) {
+        Some(Some(route)) => route,
+        _ => return,
+    };
+
+    let io_apics = apic.io_apics.lock();
+    if let Some(io_apic) = io_apics.iter().flatten().find(|io_apic| io_apic.handles(route.gsi)) {
+        let entry = io_apic.read_entry(route.gsi);
+        io_apic.write_entry(route.gsi, if masked { entry | REDIRECTION_MASKED } else { entry & !REDIRECTION_MASKED });
+    }
+}
+
+pub fn mask_isa_irq(irq: u8) {
+    set_isa_irq_masked(irq, true);
+}
+
+pub fn unmask_isa_irq(irq: u8) {
+    set_isa_irq_masked(irq, false);
+}
+
+/// `gsi`로 들어오는 신호를 이 CPU에 NMI로 전달합니다. NMI로 전달할 때는 벡터가 무시되고 엣지 트리거여야 합니다.
+/// APIC가 켜져 있지 않거나 `gsi`를 처리하는 I/O APIC가 없으면 false를 돌려줍니다.
+pub fn route_gsi_to_nmi(gsi: u32) -> bool {
+    let apic = match APIC.r#try() {
+        Some(apic) => apic,
+        None => return false,
+    };
+    let destination = u64::from(apic.local.id()) << 56;
+    let io_apics = apic.io_apics.lock();
+    match io_apics.iter().flatten().find(|io_apic| io_apic.handles(gsi)) {
+        Some(io_apic) => {
+            io_apic.write_entry(gsi, destination | REDIRECTION_DELIVERY_NMI);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn mask_gsi(gsi: u32) {
+    if let Some(apic) = APIC.r#try() {
+        let io_apics = apic.io_apics.lock();
+        if let Some(io_apic) = io_apics.iter().flatten().find(|io_apic| io_apic.handles(gsi)) {
+            io_apic.write_entry(gsi, REDIRECTION_MASKED);
+        }
+    }
+}
+
+#[test_case]
+fn test_override_claims_its_gsi() {
+    use crate::acpi::InterruptOverride;
+
+    let mut overrides = [None; acpi::ISA_IRQS];
+    overrides[0] = Some(InterruptOverride { source: 0, gsi: 2, flags: 0 });
+    // 같은 GSI로 가는 override는 자기 GSI를 빼앗지 않습니다.
+    overrides[9] = Some(InterruptOverride { source: 9, gsi: 9, flags: 0b1111 });
+    let madt = Madt {
+        local_apic_address: 0xFEE0_0000,
+        pcat_compat: true,
+        io_apics: [None; MAX_IO_APICS],
+        overrides,
+    };
+
+    let routes = isa_routes(&madt);
+    assert_eq!(routes[0].map(|route| route.gsi), Some(2));
+    assert!(routes[2].is_none());
+    assert_eq!(routes[1].map(|route| route.gsi), Some(1));
+    let irq9 = routes[9].unwrap();
+    assert!(irq9.gsi == 9 && irq9.active_low && irq9.level_triggered);
+    // 어떤 항목도 IRQ0의 GSI를 두 번 쓰지 않습니다.
+    assert_eq!(routes.iter().flatten().filter(|route| route.gsi == 2).count(), 1);
+}
+
+#[test_case]
+fn test_isa_irqs_go_through_io_apic() {
+    use crate::interrupts::{self, InterruptController, PIC_1_OFFSET};
+
+    // 테스트 커널도 init_memory를 거치므로 APIC가 켜져 있어야 합니다.
+    assert_eq!(interrupts::controller(), InterruptController::Apic);
+    let apic = APIC.r#try().expect("APIC is not active");
+    let entry = |irq: usize| {
+        let route = apic.isa_routes[irq].expect("ISA IRQ has no route");
+        let io_apics = apic.io_apics.lock();
+        let io_apic = io_apics.iter().flatten().find(|io_apic| io_apic.handles(route.gsi)).unwrap();
+        io_apic.read_entry(route.gsi)
+    };
+
+    // 타이머는 PIC를 쓸 때와 같은 벡터로 들어오고 켜져 있습니다.
+    assert_eq!(entry(0) & 0xFF, u64::from(PIC_1_OFFSET));
+    assert_eq!(entry(0) & REDIRECTION_MASKED, 0);
+    // IRQ5는 쓰는 장치가 없어서 마스크되어 있습니다.
+    assert_ne!(entry(5) & REDIRECTION_MASKED, 0);
+    unmask_isa_irq(5);
+    assert_eq!(entry(5) & REDIRECTION_MASKED, 0);
+    mask_isa_irq(5);
+    assert_ne!(entry(5) & REDIRECTION_MASKED, 0);
+}
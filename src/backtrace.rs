@@ -0,0 +1,135 @@
+//! 프레임 포인터를 따라가는 backtrace
+//!
+//! 커널은 `-C force-frame-pointers=yes`로 빌드하므로 모든 함수가 프롤로그에서 `push rbp; mov rbp, rsp`를 실행합니다.
+//! 그래서 rbp가 가리키는 곳에는 호출한 함수의 rbp가, 그 바로 위(rbp + 8)에는 돌아갈 주소가 있습니다.
+//! 이 연결 리스트를 따라가면 심볼 정보 없이도 호출 경로의 주소를 얻을 수 있습니다.
+//! 주소는 `objdump -d`나 `addr2line -e <커널 ELF>`로 함수 이름과 줄 번호로 바꿔 봅니다.
+use core::fmt;
+
+const MAX_DEPTH: usize = 32;
+// 한 함수의 스택 프레임이 이보다 크다면 rbp가 망가졌다고 봅니다.
+const MAX_FRAME_SIZE: u64 = 64 * 1024;
+
+/// 멈춘 지점의 rip, rsp, rbp에서 시작하는 backtrace. 출력할 때 스택을 따라갑니다.
+#[derive(Debug, Clone, Copy)]
+pub struct Backtrace {
+    rip: u64,
+    rsp: u64,
+    rbp: u64,
+    // 프레임을 읽어도 되는 범위 [bottom, top)
+    bottom: u64,
+    top: u64,
+}
+
+impl Backtrace {
+    pub fn new(rip: u64, rsp: u64, rbp: u64) -> Backtrace {
+        Backtrace { rip, rsp, rbp, bottom: 0, top: u64::MAX }
+    }
+
+    /// 멈춘 코드가 쓰던 스택을 알고 있으면 [bottom, top) 밖의 프레임은 읽지 않습니다.
+    /// 스택 사이의 guard page를 읽다가 다시 page fault가 나는 것을 막습니다.
+    pub fn within(self, bottom: u64, top: u64) -> Backtrace {
+        Backtrace { bottom, top, ..self }
+    }
+
+    /// 호출한 함수들의 돌아갈 주소. 첫 번째 항목(멈춘 지점의 rip)은 포함하지 않습니다.
+    pub fn return_addresses(&self) -> ReturnAddresses {
+        ReturnAddresses {
+            rbp: self.rbp,
+            // 첫 rbp는 멈춘 지점의 스택 안에 있어야 합니다.
+            lower_bound: self.rsp.max(self.bottom),
+            upper_bound: self.top,
+            depth: 0,
+        }
+    }
+}
+
+/*
+    멈춘 코드가 프레임 포인터를 쓰지 않는 어셈블리였거나 프롤로그 도중이었다면 rbp는 아무 값이나 될 수 있습니다.
+    매핑되지 않은 주소를 읽으면 page fault가 나므로, 읽기 전에 다음 조건을 확인합니다.
+    - 8바이트로 정렬되어 있어야 합니다.
+    - 스택은 아래로 자라므로 바깥 함수의 프레임은 항상 더 높은 주소에 있습니다.
+    - 이전 프레임과의 거리가 MAX_FRAME_SIZE보다 작아야 합니다.
+    - 스택의 범위를 알고 있다면 [rbp, rbp + 16)이 그 안에 있어야 합니다.
+*/
+pub struct ReturnAddresses {
+    rbp: u64,
+    lower_bound: u64,
+    upper_bound: u64,
+    depth: usize,
+}
+
+impl Iterator for ReturnAddresses {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let rbp = self.rbp;
+        if self.depth >= MAX_DEPTH
+            || rbp % 8 != 0
+            || rbp < self.lower_bound
+            || rbp - self.lower_bound > MAX_FRAME_SIZE
+            || rbp.checked_add(16).map_or(true, |end| end > self.upper_bound)
+        {
+            return None;
+        }
+
+        let (saved_rbp, return_address) = unsafe {
+            let frame = rbp as *const u64;
+            (frame.read_volatile(), frame.add(1).read_volatile())
+        };
+        if return_address == 0 {
+            return None;
+        }
+        self.depth += 1;
+        self.lower_bound = rbp + 16;
+        self.rbp = saved_rbp;
+        Some(return_address)
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "  #0  {:#018x}", self.rip)?;
+        for (depth, address) in self.return_addresses().enumerate() {
+            writeln!(f, "  #{:<2} {:#018x}", depth + 1, address)?;
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_walk_frame_chain() {
+    // 높은 주소 쪽이 바깥 함수인 가짜 스택: [rbp, 돌아갈 주소] 쌍 세 개
+    let mut stack = [0u64; 12];
+    let base = stack.as_ptr() as u64;
+    stack[0] = base + 4 * 8;
+    stack[1] = 0x1111;
+    stack[4] = base + 8 * 8;
+    stack[5] = 0x2222;
+    // 마지막 프레임의 rbp가 아래를 가리키면 거기서 멈춥니다.
+    stack[8] = base;
+    stack[9] = 0x3333;
+
+    let backtrace = Backtrace::new(0x1000, base, base);
+    let mut addresses = backtrace.return_addresses();
+    assert_eq!(addresses.next(), Some(0x1111));
+    assert_eq!(addresses.next(), Some(0x2222));
+    assert_eq!(addresses.next(), Some(0x3333));
+    assert_eq!(addresses.next(), None);
+}
+
+#[test_case]
+fn test_walk_stops_at_stack_top() {
+    let mut stack = [0u64; 8];
+    let base = stack.as_ptr() as u64;
+    stack[0] = base + 4 * 8;
+    stack[1] = 0x1111;
+    // 두 번째 프레임은 스택의 꼭대기(base + 32) 위에 있으므로 읽지 않습니다.
+    stack[4] = base;
+    stack[5] = 0x2222;
+
+    let backtrace = Backtrace::new(0x1000, base, base).within(base, base + 4 * 8);
+    let mut addresses = backtrace.return_addresses();
+    assert_eq!(addresses.next(), Some(0x1111));
+    assert_eq!(addresses.next(), None);
+}
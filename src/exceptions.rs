@@ -0,0 +1,310 @@
+//! CPU 예외 핸들러
+//!
+//! 0~31번 벡터는 CPU 예외를 위해 예약되어 있습니다. 핸들러가 등록되지 않은 예외는 곧바로 double fault로
+//! 이어지기 때문에 원래 원인을 알 수 없게 됩니다. 여기서는 모든 아키텍처 예외에 핸들러를 등록하고,
+//! 오류 코드를 해석한 결과를 `CrashReport`로 정리해서 보여줍니다.
+use core::fmt;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{
+    InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode, SelectorErrorCode,
+};
+use x86_64::VirtAddr;
+use crate::backtrace::Backtrace;
+use crate::gdt::IstStack;
+use crate::stack::{self, Stack};
+use crate::{interrupt_stats, println, usermode};
+
+/// 예외와 함께 전달되는 오류 정보
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorInfo {
+    /// 오류 코드를 넘겨주지 않는 예외
+    None,
+    /// 의미가 정해지지 않은 오류 코드 (alignment check 등)
+    Code(u64),
+    /// 세그먼트 셀렉터를 가리키는 오류 코드 (#TS, #NP, #SS, #GP)
+    Selector(SelectorErrorCode),
+    /// page fault의 오류 플래그와 CR2에 저장된 접근 주소
+    PageFault {
+        flags: PageFaultErrorCode,
+        address: VirtAddr,
+    },
+}
+
+impl fmt::Display for ErrorInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorInfo::None => Ok(()),
+            ErrorInfo::Code(code) => writeln!(f, "Error Code: {:#x}", code),
+            ErrorInfo::Selector(selector) if selector.is_null() => {
+                writeln!(f, "Error Code: 0 (not segment related)")
+            }
+            ErrorInfo::Selector(selector) => writeln!(
+                f,
+                "Selector: {:?} index {}{}",
+                selector.descriptor_table(),
+                selector.index(),
+                if selector.external() { " (external event)" } else { "" },
+            ),
+            ErrorInfo::PageFault { flags, address } => {
+                writeln!(f, "Accessed Address: {:?}", address)?;
+                writeln!(f, "Error Code: {:?}", flags)
+            }
+        }
+    }
+}
+
+/// 예외 하나에 대한 보고서. panic 메시지로 출력됩니다.
+pub struct CrashReport<'a> {
+    pub vector: u8,
+    pub name: &'static str,
+    pub error: ErrorInfo,
+    pub stack_frame: &'a InterruptStackFrame,
+}
+
+impl fmt::Display for CrashReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "EXCEPTION: {} (vector {})", self.name, self.vector)?;
+        write!(f, "{}", self.error)?;
+        write!(f, "{:#?}", self.stack_frame)
+    }
+}
+
+/*
+    돌아갈 수 없는 예외도 통계에는 남겨 둡니다. 걸린 시간은 진입부터 panic 직전까지입니다.
+    유저 모드에서 일어난 예외는 커널의 잘못이 아니므로 panic하지 않고 그 프로그램만 끝냅니다.
+    double fault와 machine check는 유저 모드에서 일어났더라도 커널이나 하드웨어의 문제이므로 panic합니다.
+*/
+fn crash(report: CrashReport, entry: u64) -> ! {
+    interrupt_stats::record(report.vector, entry);
+    if from_user_mode(report.stack_frame) && !matches!(report.vector, 8 | 18) {
+        println!("user program killed\n{}", report);
+        unsafe { usermode::kill(report.vector) };
+    }
+    panic!("{}", report);
+}
+
+// 예외가 난 코드의 특권 수준은 cs 셀렉터의 RPL(하위 2비트)입니다.
+fn from_user_mode(stack_frame: &InterruptStackFrame) -> bool {
+    stack_frame.code_segment & 3 == 3
+}
+
+/// 커널 스택이 넘쳐 guard page에 닿았을 때의 보고서
+pub struct StackOverflowReport<'a> {
+    pub vector: u8,
+    pub stack: Stack,
+    /// guard page 안에서 접근한 주소
+    pub address: VirtAddr,
+    pub stack_frame: &'a InterruptStackFrame,
+    pub backtrace: Backtrace,
+}
+
+impl fmt::Display for StackOverflowReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stack = &self.stack;
+        writeln!(f, "kernel stack overflow on the {} stack (vector {})", stack.name(), self.vector)?;
+        writeln!(
+            f,
+            "Stack: {:#x}..{:#x} ({} KiB), guard page at {:#x}",
+            stack.bottom().as_u64(),
+            stack.top().as_u64(),
+            (stack.top() - stack.bottom()) / 1024,
+            stack.guard_page().start_address().as_u64(),
+        )?;
+        writeln!(
+            f,
+            "Depth reached: {} bytes (accessed {:#x})",
+            stack.top().as_u64().saturating_sub(self.address.as_u64()),
+            self.address.as_u64(),
+        )?;
+        writeln!(f, "{:#?}", self.stack_frame)?;
+        write!(f, "Backtrace:\n{}", self.backtrace)
+    }
+}
+
+/*
+    예외가 난 코드의 rbp
+
+    x86-interrupt 함수도 프레임 포인터를 쓰므로 프롤로그에서 rbp를 push합니다.
+    그래서 핸들러의 rbp가 가리키는 곳에 예외가 난 코드의 rbp가 있습니다. 핸들러 자신의 rbp를 읽어야 하므로 매크로로 만듭니다.
+    값이 틀릴 수도 있으므로, 어느 스택에서 난 예외인지 알 때는 Backtrace::within으로 그 스택 밖을 읽지 않게 합니다.
+*/
+macro_rules! interrupted_rbp {
+    () => {{
+        let rbp: u64;
+        unsafe {
+            core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+            *(rbp as *const u64)
+        }
+    }};
+}
+
+fn stack_overflow(
+    vector: u8,
+    stack: Stack,
+    address: VirtAddr,
+    stack_frame: &InterruptStackFrame,
+    rbp: u64,
+    entry: u64,
+) -> ! {
+    // 넘친 스택의 바로 아래는 guard page이고 위로는 다른 스택의 guard page가 이어지므로 이 스택 안만 따라갑니다.
+    let backtrace = Backtrace::new(
+        stack_frame.instruction_pointer.as_u64(),
+        stack_frame.stack_pointer.as_u64(),
+        rbp,
+    )
+    .within(stack.bottom().as_u64(), stack.top().as_u64());
+    interrupt_stats::record(vector, entry);
+    panic!("{}", StackOverflowReport { vector, stack, address, stack_frame, backtrace });
+}
+
+/*
+    대부분의 예외 핸들러는 모양이 같기 때문에 매크로로 만듭니다.
+    - 오류 코드가 없는 예외
+    - 세그먼트 셀렉터 오류 코드를 가진 예외
+    - 해석할 수 없는 오류 코드를 가진 예외
+*/
+macro_rules! exception_handler {
+    ($handler:ident, $vector:expr, $name:expr) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame) {
+            let entry = interrupt_stats::enter();
+            crash(CrashReport {
+                vector: $vector,
+                name: $name,
+                error: ErrorInfo::None,
+                stack_frame: &stack_frame,
+            }, entry);
+        }
+    };
+    ($handler:ident, $vector:expr, $name:expr, selector) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame, error_code: u64) {
+            let entry = interrupt_stats::enter();
+            crash(CrashReport {
+                vector: $vector,
+                name: $name,
+                error: ErrorInfo::Selector(SelectorErrorCode::new_truncate(error_code)),
+                stack_frame: &stack_frame,
+            }, entry);
+        }
+    };
+    ($handler:ident, $vector:expr, $name:expr, code) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame, error_code: u64) {
+            let entry = interrupt_stats::enter();
+            crash(CrashReport {
+                vector: $vector,
+                name: $name,
+                error: ErrorInfo::Code(error_code),
+                stack_frame: &stack_frame,
+            }, entry);
+        }
+    };
+}
+
+exception_handler!(divide_error_handler, 0, "DIVIDE ERROR");
+exception_handler!(overflow_handler, 4, "OVERFLOW");
+exception_handler!(bound_range_exceeded_handler, 5, "BOUND RANGE EXCEEDED");
+exception_handler!(invalid_opcode_handler, 6, "INVALID OPCODE");
+exception_handler!(device_not_available_handler, 7, "DEVICE NOT AVAILABLE");
+exception_handler!(invalid_tss_handler, 10, "INVALID TSS", selector);
+exception_handler!(segment_not_present_handler, 11, "SEGMENT NOT PRESENT", selector);
+exception_handler!(stack_segment_fault_handler, 12, "STACK SEGMENT FAULT", selector);
+exception_handler!(general_protection_fault_handler, 13, "GENERAL PROTECTION FAULT", selector);
+exception_handler!(x87_floating_point_handler, 16, "x87 FLOATING POINT");
+exception_handler!(alignment_check_handler, 17, "ALIGNMENT CHECK", code);
+exception_handler!(simd_floating_point_handler, 19, "SIMD FLOATING POINT");
+exception_handler!(virtualization_handler, 20, "VIRTUALIZATION");
+exception_handler!(vmm_communication_handler, 29, "VMM COMMUNICATION", code);
+exception_handler!(security_exception_handler, 30, "SECURITY EXCEPTION", code);
+
+/*
+    page fault가 발생하면 CPU는 접근하려던 가상 주소를 CR2 레지스터에 저장합니다.
+    오류 코드는 메모리 접근의 종류(읽기/쓰기, 유저 모드, 명령어 인출 등)를 알려줍니다.
+    page fault는 자기 IST 스택에서 처리되므로, 커널 스택이 넘쳐서 guard page에 닿은 경우에도 여기까지 올 수 있습니다.
+*/
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode)
+{
+    let entry = interrupt_stats::enter();
+    let address = Cr2::read();
+    // 유저 프로그램이 guard page를 건드린 것은 커널 스택이 넘친 것이 아닙니다.
+    if let Some(stack) = stack::guarded_by(address).filter(|_| !from_user_mode(&stack_frame)) {
+        stack_overflow(14, stack, address, &stack_frame, interrupted_rbp!(), entry);
+    }
+    crash(CrashReport {
+        vector: 14,
+        name: "PAGE FAULT",
+        error: ErrorInfo::PageFault {
+            flags: error_code,
+            address,
+        },
+        stack_frame: &stack_frame,
+    }, entry);
+}
+
+/*
+    처리기는 짧은 오류 메시지를 출력하고 예외 Stack Frame을 Dump하고, double fault handler의 오류 코드는 항상 0이므로 인쇄할 필요가 없습니다.
+    breakpoint handler의 한 가지 차이점은 double fault handler가 프로그램의 실행 순서를 변경하여 다른 명령을 실행 수 있도록 하는 것입니다.
+    그 이유는 x86_64 아키텍처가 double fault exception로부터의 반환을 허용하지 않기 때문입니다.
+*/
+
+// 호출 규약과 함수를 정의합니다(x86-interrupt, double_fault_handler)
+// page fault 핸들러를 부를 수 없었던 경우(IDT를 바꿔 끼운 테스트 등)에는 rsp가 guard page 바로 위에 멈춰 있습니다.
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: InterruptStackFrame, _error_code: u64) -> !
+{
+    let entry = interrupt_stats::enter();
+    let rsp = stack_frame.stack_pointer;
+    if let Some(stack) = stack::guarded_by(rsp - 1u64) {
+        stack_overflow(8, stack, rsp - 8u64, &stack_frame, interrupted_rbp!(), entry);
+    }
+    crash(CrashReport {
+        vector: 8,
+        name: "DOUBLE FAULT",
+        error: ErrorInfo::None,
+        stack_frame: &stack_frame,
+    }, entry);
+}
+
+// machine check는 하드웨어 오류이므로 돌아갈 수 없습니다.
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    let entry = interrupt_stats::enter();
+    crash(CrashReport {
+        vector: 18,
+        name: "MACHINE CHECK",
+        error: ErrorInfo::None,
+        stack_frame: &stack_frame,
+    }, entry);
+}
+
+/// 모든 예외 핸들러를 IDT에 등록합니다. breakpoint와 debug는 monitor 모듈이, NMI는 watchdog 모듈이 등록합니다.
+pub fn register(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available.set_handler_fn(device_not_available_handler);
+    unsafe {
+        idt.double_fault.set_handler_fn(double_fault_handler)
+            .set_stack_index(IstStack::DoubleFault.index());
+        idt.page_fault.set_handler_fn(page_fault_handler)
+            .set_stack_index(IstStack::PageFault.index());
+        idt.machine_check.set_handler_fn(machine_check_handler)
+            .set_stack_index(IstStack::MachineCheck.index());
+    }
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+    idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    idt.vmm_communication_exception.set_handler_fn(vmm_communication_handler);
+    idt.security_exception.set_handler_fn(security_exception_handler);
+}
+
+#[test_case]
+fn test_selector_error_report() {
+    // GDT의 3번 디스크립터 (3 << 3)
+    let error = ErrorInfo::Selector(SelectorErrorCode::new_truncate(0x18));
+    assert_eq!(crate::test_support::Message::of(&error).as_bytes(), b"Selector: Gdt index 3\n");
+}
@@ -0,0 +1,183 @@
+//! 물리 프레임 할당기
+//!
+//! 부트로더가 넘겨준 메모리 맵에서 Usable로 표시된 4 KiB 프레임을 나눠 주고 돌려받습니다.
+//! 힙은 이 할당기가 준 프레임 위에 만들어지므로 힙을 쓰지 않고, 프레임마다 1비트를 쓰는 비트맵을 static 배열로 둡니다. 비트가 1이면 빈 프레임입니다.
+//! (0으로 시작하는 배열이어야 커널 이미지가 아니라 .bss에 들어가므로, 사용 중인 프레임을 0으로 표시합니다.)
+//! 원래 Usable이었던 프레임도 같은 크기의 비트맵에 따로 적어 두고, 그 밖의 프레임을 돌려받으면 panic합니다.
+//! 비트맵은 MAX_PHYSICAL_MEMORY까지만 다루므로 그보다 높은 주소의 메모리는 쓰지 않고 개수만 세어 둡니다.
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::fmt;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+const FRAME_SIZE: u64 = 4096;
+const MAX_PHYSICAL_MEMORY: u64 = 4 << 30;
+const MAX_FRAMES: usize = (MAX_PHYSICAL_MEMORY / FRAME_SIZE) as usize;
+const WORDS: usize = MAX_FRAMES / 64;
+
+struct Bitmap {
+    words: [u64; WORDS],
+    // 메모리 맵에서 Usable이었던 프레임. 돌려받는 프레임이 원래 나눠 줄 수 있던 것인지 확인합니다.
+    usable_words: [u64; WORDS],
+    // 빈 프레임을 찾기 시작할 워드. 이보다 앞의 워드에는 빈 프레임이 없습니다.
+    next_word: usize,
+    usable: usize,
+    free: usize,
+    // 비트맵 범위를 넘어서 버린 Usable 프레임 수
+    ignored: usize,
+}
+
+impl Bitmap {
+    const fn new() -> Bitmap {
+        Bitmap { words: [0; WORDS], usable_words: [0; WORDS], next_word: WORDS, usable: 0, free: 0, ignored: 0 }
+    }
+
+    fn is_free(&self, frame: usize) -> bool {
+        self.words[frame / 64] & (1 << (frame % 64)) != 0
+    }
+
+    fn is_usable(&self, frame: usize) -> bool {
+        frame < MAX_FRAMES && self.usable_words[frame / 64] & (1 << (frame % 64)) != 0
+    }
+
+    // 사용 중인 프레임을 빈 프레임으로 표시합니다. 이미 비어 있으면 false를 돌려줍니다.
+    fn release(&mut self, frame: usize) -> bool {
+        if self.is_free(frame) {
+            return false;
+        }
+        self.words[frame / 64] |= 1 << (frame % 64);
+        self.free += 1;
+        self.next_word = self.next_word.min(frame / 64);
+        true
+    }
+
+    fn allocate(&mut self) -> Option<usize> {
+        let index = (self.next_word..WORDS).find(|&index| self.words[index] != 0)?;
+        self.next_word = index;
+        let bit = self.words[index].trailing_zeros() as usize;
+        self.words[index] &= !(1 << bit);
+        let frame = index * 64 + bit;
+        self.free -= 1;
+        Some(frame)
+    }
+}
+
+static BITMAP: Mutex<Bitmap> = Mutex::new(Bitmap::new());
+
+/*
+    메모리 맵의 Usable 영역을 비트맵에 빈 프레임으로 표시합니다.
+    커널, 페이지 테이블, 부트로더가 쓰는 영역은 다른 종류로 표시되어 있으므로 건드리지 않습니다.
+    0번 프레임은 null 포인터와 구분하기 어려우므로 Usable이어도 쓰지 않습니다.
+*/
+/// 메모리 맵으로 할당기를 초기화합니다. 두 번 호출하면 panic합니다.
+pub fn init(memory_map: &MemoryMap) {
+    let mut bitmap = BITMAP.lock();
+    assert_eq!(bitmap.usable, 0, "frame allocator is already initialized");
+
+    for region in memory_map.iter().filter(|region| region.region_type == MemoryRegionType::Usable) {
+        for frame in region.range.start_frame_number..region.range.end_frame_number {
+            if frame == 0 {
+                continue;
+            }
+            if frame >= MAX_FRAMES as u64 {
+                bitmap.ignored += 1;
+                continue;
+            }
+            let frame = frame as usize;
+            bitmap.usable_words[frame / 64] |= 1 << (frame % 64);
+            if bitmap.release(frame) {
+                bitmap.usable += 1;
+            }
+        }
+    }
+}
+
+/// 프레임 사용 현황
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    /// 메모리 맵에서 Usable이었던 프레임 수
+    pub usable: usize,
+    pub free: usize,
+    /// 비트맵이 다루지 못해 버린 프레임 수
+    pub ignored: usize,
+}
+
+pub fn stats() -> FrameStats {
+    let bitmap = BITMAP.lock();
+    FrameStats { usable: bitmap.usable, free: bitmap.free, ignored: bitmap.ignored }
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} KiB free of {} KiB usable",
+            self.free as u64 * FRAME_SIZE / 1024,
+            self.usable as u64 * FRAME_SIZE / 1024,
+        )?;
+        if self.ignored != 0 {
+            write!(f, " ({} KiB above {} GiB ignored)", self.ignored as u64 * FRAME_SIZE / 1024, MAX_PHYSICAL_MEMORY >> 30)?;
+        }
+        Ok(())
+    }
+}
+
+/// 전역 비트맵에서 프레임을 나눠 주는 할당기. 상태가 없으므로 필요한 곳에서 만들어 씁니다.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BitmapFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        let frame = BITMAP.lock().allocate()?;
+        Some(PhysFrame::containing_address(PhysAddr::new(frame as u64 * FRAME_SIZE)))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    /// 할당하지 않은 프레임(커널 이미지, 부트로더의 페이지 테이블 등)이나 이미 돌려받은 프레임을 넘기면 panic합니다.
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        let number = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        let mut bitmap = BITMAP.lock();
+        assert!(bitmap.is_usable(number), "{:?} was not allocated by the frame allocator", frame);
+        assert!(bitmap.release(number), "{:?} is freed twice", frame);
+    }
+}
+
+#[test_case]
+fn test_allocate_and_free_frames() {
+    let mut allocator = BitmapFrameAllocator;
+    let before = stats();
+    assert!(before.free > 0, "the test kernel starts with the memory map");
+
+    let first = allocator.allocate_frame().unwrap();
+    let second = allocator.allocate_frame().unwrap();
+    assert_ne!(first, second);
+    assert_ne!(first.start_address().as_u64(), 0);
+    assert_eq!(stats().free, before.free - 2);
+
+    // 돌려받은 프레임은 다시 나눠 줍니다.
+    unsafe { allocator.deallocate_frame(first) };
+    assert_eq!(allocator.allocate_frame(), Some(first));
+    unsafe {
+        allocator.deallocate_frame(first);
+        allocator.deallocate_frame(second);
+    }
+    assert_eq!(stats(), before);
+}
+
+#[test_case]
+fn test_only_usable_frames_can_be_freed() {
+    // 커널 코드가 있는 프레임은 메모리 맵에서 Usable이 아니므로 돌려받을 수 없습니다.
+    let code = x86_64::VirtAddr::new(init as usize as u64);
+    let kernel_frame = crate::memory::translate_addr(code).unwrap().as_u64() / FRAME_SIZE;
+    let frame = BitmapFrameAllocator.allocate_frame().unwrap();
+    {
+        let bitmap = BITMAP.lock();
+        assert!(!bitmap.is_usable(kernel_frame as usize));
+        assert!(!bitmap.is_usable(0));
+        assert!(!bitmap.is_usable(MAX_FRAMES));
+        assert!(bitmap.is_usable((frame.start_address().as_u64() / FRAME_SIZE) as usize));
+    }
+    unsafe { BitmapFrameAllocator.deallocate_frame(frame) };
+}
@@ -1,20 +1,53 @@
 use x86_64::VirtAddr;
 use x86_64::structures::tss::TaskStateSegment;
 use lazy_static::lazy_static;
+use crate::stack;
 
-pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/*
+    IST (Interrupt Stack Table)
+
+    IST 항목을 지정한 예외는 지금 스택의 상태와 상관없이 항상 TSS에 적힌 별도의 스택에서 처리됩니다.
+    커널 스택이 넘쳐서 생긴 예외나, 아무 때나 끼어드는 NMI와 machine check는 지금 스택을 믿을 수 없으므로 IST를 씁니다.
+    모든 스택은 stack 모듈이 guard page와 함께 만듭니다.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IstStack {
+    DoubleFault,
+    Nmi,
+    MachineCheck,
+    PageFault,
+}
+
+impl IstStack {
+    pub const ALL: [IstStack; 4] = [IstStack::DoubleFault, IstStack::Nmi, IstStack::MachineCheck, IstStack::PageFault];
+
+    /// IDT의 set_stack_index에 넘길 번호 (TSS의 interrupt_stack_table 인덱스)
+    pub const fn index(self) -> u16 {
+        self as u16
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            IstStack::DoubleFault => "double fault",
+            IstStack::Nmi => "NMI",
+            IstStack::MachineCheck => "machine check",
+            IstStack::PageFault => "page fault",
+        }
+    }
+}
+
+// IST 스택 하나의 크기 (페이지 수). 모든 IST 핸들러가 보고서를 출력하므로 같은 크기를 씁니다.
+const IST_STACK_PAGES: usize = 5;
+const PRIVILEGE_STACK_PAGES: usize = 5;
 
 lazy_static! {
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
+        for ist in IstStack::ALL {
+            tss.interrupt_stack_table[usize::from(ist.index())] = stack::allocate(ist.name(), IST_STACK_PAGES).top();
+        }
+        // 유저 모드(ring 3)에서 인터럽트나 시스템 콜로 커널에 들어오면 CPU가 이 스택으로 바꿉니다.
+        tss.privilege_stack_table[0] = stack::allocate("ring 0", PRIVILEGE_STACK_PAGES).top();
         tss
     };
 }
@@ -22,27 +55,54 @@ lazy_static! {
 use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor};
 use x86_64::structures::gdt::SegmentSelector;
 
+/*
+    디스크립터의 순서는 SYSRET 명령이 정한 규칙을 따릅니다.
+    SYSRET은 STAR 레지스터에 적힌 셀렉터 하나에서 유저 데이터(+8)와 유저 코드(+16) 셀렉터를 계산하므로,
+    유저 데이터 세그먼트 바로 뒤에 유저 코드 세그먼트가 와야 합니다. (SYSCALL도 커널 코드 바로 뒤를 커널 데이터로 씁니다.)
+    0: null, 1: 커널 코드, 2: 커널 데이터, 3: 유저 데이터, 4: 유저 코드, 5~6: TSS
+*/
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+        let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
         let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (gdt, Selectors { code_selector, tss_selector })
+        (gdt, Selectors { code_selector, data_selector, user_data_selector, user_code_selector, tss_selector })
     };
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+/// GDT에 들어 있는 세그먼트의 셀렉터. 유저 세그먼트의 셀렉터는 RPL이 3입니다.
+#[derive(Debug, Clone, Copy)]
+pub struct Selectors {
+    pub code_selector: SegmentSelector,
+    pub data_selector: SegmentSelector,
+    pub user_data_selector: SegmentSelector,
+    pub user_code_selector: SegmentSelector,
+    pub tss_selector: SegmentSelector,
+}
+
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
+
+/// 유저 모드에서 커널로 들어올 때 사용하는 스택(privilege_stack_table[0])의 꼭대기
+pub fn privilege_stack_top() -> VirtAddr {
+    TSS.privilege_stack_table[0]
 }
 
 pub fn init() {
     use x86_64::instructions::tables::load_tss;
-    use x86_64::instructions::segmentation::{CS, Segment};
+    use x86_64::instructions::segmentation::{CS, DS, ES, SS, Segment};
     
     GDT.0.load();
     unsafe {
         CS::set_reg(GDT.1.code_selector);
+        // 부트로더의 GDT를 가리키던 셀렉터가 남아 있지 않도록 데이터 세그먼트도 다시 불러옵니다.
+        SS::set_reg(GDT.1.data_selector);
+        DS::set_reg(GDT.1.data_selector);
+        ES::set_reg(GDT.1.data_selector);
         load_tss(GDT.1.tss_selector);
     }
 }
\ No newline at end of file
@@ -0,0 +1,166 @@
+//! HPET (High Precision Event Timer)
+//!
+//! HPET는 최소 10 MHz로 증가하는 64비트(또는 32비트) 메인 카운터를 가진 타이머입니다.
+//! 레지스터는 MMIO로 접근하며 주소는 ACPI의 HPET 테이블로 알아냅니다.
+//! 메인 카운터는 TSC 보정의 기준 시계로 사용하고, 비교기(comparator)는 워치독처럼 주기적인 인터럽트가 필요할 때 사용합니다.
+use core::ptr;
+use core::time::Duration;
+use spin::Once;
+use x86_64::VirtAddr;
+use crate::acpi;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HpetError {
+    /// ACPI 테이블에서 HPET 테이블을 찾지 못했습니다.
+    TableNotFound,
+    /// 카운터 주기가 0이거나 사양의 최댓값(100ns)보다 깁니다.
+    InvalidPeriod(u32),
+    /// 해당 번호의 비교기가 없습니다.
+    NoSuchTimer(u8),
+    /// 비교기가 주기 모드를 지원하지 않습니다.
+    PeriodicUnsupported(u8),
+    /// 비교기를 I/O APIC의 해당 입력에 연결할 수 없습니다.
+    RouteUnsupported(u32),
+}
+
+mod reg {
+    pub const CAPABILITIES: usize = 0x000;
+    pub const CONFIGURATION: usize = 0x010;
+    pub const MAIN_COUNTER: usize = 0x0F0;
+
+    // 비교기 n의 레지스터는 0x100부터 0x20 간격으로 있습니다.
+    pub const fn timer_configuration(timer: u8) -> usize {
+        0x100 + 0x20 * timer as usize
+    }
+
+    pub const fn timer_comparator(timer: u8) -> usize {
+        0x108 + 0x20 * timer as usize
+    }
+}
+
+const CONFIGURATION_ENABLE: u64 = 1 << 0;
+
+/*
+    비교기 설정 레지스터
+    - 비트 1: 1이면 레벨 트리거, 0이면 엣지 트리거
+    - 비트 2: 인터럽트 켜기
+    - 비트 3: 주기 모드
+    - 비트 4: 주기 모드를 지원하는지 (읽기 전용)
+    - 비트 6: 주기 모드에서 다음 비교기 쓰기가 누산기(accumulator)가 아니라 비교 값을 바꾸도록 합니다.
+    - 비트 9~13: 연결할 I/O APIC 입력(GSI)
+    - 비트 32~63: 연결할 수 있는 I/O APIC 입력의 비트맵 (읽기 전용)
+*/
+const TIMER_LEVEL_TRIGGERED: u64 = 1 << 1;
+const TIMER_INTERRUPT_ENABLE: u64 = 1 << 2;
+const TIMER_PERIODIC: u64 = 1 << 3;
+const TIMER_PERIODIC_CAPABLE: u64 = 1 << 4;
+const TIMER_VALUE_SET: u64 = 1 << 6;
+const TIMER_ROUTE_SHIFT: u64 = 9;
+const TIMER_ROUTE_MASK: u64 = 0x1F << TIMER_ROUTE_SHIFT;
+// 사양상 카운터 주기는 100ns(= 10^8 펨토초) 이하여야 합니다.
+const MAX_PERIOD_FS: u32 = 100_000_000;
+const FEMTOSECONDS_PER_SECOND: u64 = 1_000_000_000_000_000;
+
+pub struct Hpet {
+    base: VirtAddr,
+    period_fs: u32,
+}
+
+impl Hpet {
+    unsafe fn read(&self, register: usize) -> u64 {
+        ptr::read_volatile((self.base + register).as_ptr())
+    }
+
+    unsafe fn write(&self, register: usize, value: u64) {
+        ptr::write_volatile((self.base + register).as_mut_ptr(), value);
+    }
+
+    pub fn counter(&self) -> u64 {
+        unsafe { self.read(reg::MAIN_COUNTER) }
+    }
+
+    /// 카운터가 1 증가하는 데 걸리는 시간 (펨토초)
+    pub fn period_fs(&self) -> u32 {
+        self.period_fs
+    }
+
+    pub fn frequency(&self) -> u64 {
+        FEMTOSECONDS_PER_SECOND / u64::from(self.period_fs)
+    }
+
+    /// 비교기의 수. 능력 레지스터의 비트 8~12에 (개수 - 1)이 들어 있습니다.
+    pub fn timers(&self) -> u8 {
+        ((unsafe { self.read(reg::CAPABILITIES) } >> 8) & 0x1F) as u8 + 1
+    }
+
+    /// 비교기를 연결할 수 있는 I/O APIC 입력의 비트맵. n번 비트가 GSI n입니다.
+    pub fn route_capabilities(&self, timer: u8) -> Result<u32, HpetError> {
+        if timer >= self.timers() {
+            return Err(HpetError::NoSuchTimer(timer));
+        }
+        Ok((unsafe { self.read(reg::timer_configuration(timer)) } >> 32) as u32)
+    }
+
+    /// 비교기를 `gsi`에 엣지 트리거로 연결하고 `period`마다 인터럽트를 보내게 합니다.
+    pub fn start_periodic(&self, timer: u8, gsi: u32, period: Duration) -> Result<(), HpetError> {
+        if gsi >= 32 || self.route_capabilities(timer)? & (1 << gsi) == 0 {
+            return Err(HpetError::RouteUnsupported(gsi));
+        }
+        let register = reg::timer_configuration(timer);
+        let configuration = unsafe { self.read(register) };
+        if configuration & TIMER_PERIODIC_CAPABLE == 0 {
+            return Err(HpetError::PeriodicUnsupported(timer));
+        }
+
+        let ticks = (period.as_nanos() * 1_000_000 / u128::from(self.period_fs)).max(1) as u64;
+        let configuration = (configuration & !TIMER_ROUTE_MASK & !TIMER_LEVEL_TRIGGERED)
+            | u64::from(gsi) << TIMER_ROUTE_SHIFT
+            | TIMER_INTERRUPT_ENABLE
+            | TIMER_PERIODIC
+            | TIMER_VALUE_SET;
+        unsafe {
+            self.write(register, configuration);
+            // VALUE_SET 다음의 첫 쓰기는 첫 만료 시각이고, 두 번째 쓰기는 주기가 됩니다.
+            self.write(reg::timer_comparator(timer), self.counter() + ticks);
+            self.write(reg::timer_comparator(timer), ticks);
+        }
+        Ok(())
+    }
+
+    pub fn stop(&self, timer: u8) {
+        if timer < self.timers() {
+            let register = reg::timer_configuration(timer);
+            unsafe {
+                let configuration = self.read(register);
+                self.write(register, configuration & !TIMER_INTERRUPT_ENABLE & !TIMER_PERIODIC);
+            }
+        }
+    }
+}
+
+static HPET: Once<Hpet> = Once::new();
+
+/// ACPI의 HPET 테이블을 읽고 메인 카운터를 켭니다.
+///
+/// # Safety
+/// 물리 메모리 전체가 `physical_memory_offset`부터 매핑되어 있어야 하고, `acpi::init`이 먼저 호출되어 있어야 합니다.
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> Result<(), HpetError> {
+    let address = acpi::hpet_address().ok_or(HpetError::TableNotFound)?;
+    let base = physical_memory_offset + address.as_u64();
+
+    // 상위 32비트가 카운터 주기입니다.
+    let period_fs = (ptr::read_volatile((base + reg::CAPABILITIES).as_ptr::<u64>()) >> 32) as u32;
+    if period_fs == 0 || period_fs > MAX_PERIOD_FS {
+        return Err(HpetError::InvalidPeriod(period_fs));
+    }
+
+    let hpet = Hpet { base, period_fs };
+    let configuration = hpet.read(reg::CONFIGURATION);
+    hpet.write(reg::CONFIGURATION, configuration | CONFIGURATION_ENABLE);
+    HPET.call_once(|| hpet);
+    Ok(())
+}
+
+pub fn hpet() -> Option<&'static Hpet> {
+    HPET.r#try()
+}
@@ -0,0 +1,63 @@
+//! 입력 이벤트 큐
+//!
+//! 키보드, 마우스 같은 입력 장치의 드라이버가 (작업 큐에서) 이벤트를 넣고, 나머지 커널 코드가 꺼내 씁니다.
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::keyboard::KeyEvent;
+use crate::mouse::MouseEvent;
+use crate::ring_buffer::RingBuffer;
+use crate::workqueue;
+
+const QUEUE_SIZE: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Key(KeyEvent),
+    Mouse(MouseEvent),
+}
+
+static EVENTS: Mutex<RingBuffer<InputEvent, QUEUE_SIZE>> = Mutex::new(RingBuffer::new());
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/*
+    push는 키보드와 마우스 드라이버의 작업 큐 항목에서만, 인터럽트가 켜진 상태로 호출됩니다.
+    EVENTS를 잠그는 인터럽트 핸들러가 없으므로 잠금을 쥔 채 인터럽트에 중단되어도 deadlock이 생기지 않고,
+    push와 poll은 인터럽트를 끄지 않고 잠급니다.
+*/
+pub(crate) fn push(event: InputEvent) {
+    if EVENTS.lock().push(event).is_err() {
+        // 큐가 가득 차면 새 이벤트를 버립니다.
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 대기 중인 이벤트가 있으면 꺼내고, 없으면 곧바로 `None`을 반환합니다.
+pub fn poll() -> Option<InputEvent> {
+    EVENTS.lock().pop()
+}
+
+/// 이벤트가 들어올 때까지 CPU를 쉬게 하면서 기다립니다.
+/// 키 입력의 해석도 작업 큐에서 이루어지므로, 기다리는 동안 작업 큐를 실행합니다.
+pub fn wait() -> InputEvent {
+    loop {
+        workqueue::run_pending();
+        // 큐 확인과 hlt 사이에 인터럽트가 끼어들면 이벤트나 작업을 놓친 채 잠들 수 있으므로,
+        // 인터럽트를 끈 채 확인하고 enable_and_hlt로 한 번에 잠듭니다.
+        interrupts::disable();
+        if let Some(event) = EVENTS.lock().pop() {
+            interrupts::enable();
+            return event;
+        }
+        if !workqueue::is_empty() {
+            interrupts::enable();
+            continue;
+        }
+        interrupts::enable_and_hlt();
+    }
+}
+
+/// 큐가 가득 차서 버려진 이벤트의 수
+pub fn dropped_events() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
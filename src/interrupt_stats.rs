@@ -0,0 +1,150 @@
+//! 인터럽트 통계
+//!
+//! IDT의 모든 핸들러는 진입할 때 TSC를 읽고, EOI를 보낸 뒤(또는 처리를 마친 뒤) `record`를 호출합니다.
+//! 벡터마다 발생 횟수와 진입부터 EOI까지 걸린 시간의 최소, 최대, 합계를 모아 두었다가
+//! 리눅스의 /proc/interrupts와 비슷한 표로 보여줍니다.
+//! 타이머가 멈췄는지, 어떤 장치가 인터럽트를 폭주시키는지 확인할 때 사용합니다.
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use crate::interrupts::{IRQ_LINES, PIC_1_OFFSET};
+use crate::{apic, syscall, tsc};
+
+const VECTORS: usize = 256;
+
+// 핸들러 안에서도 잠금 없이 갱신할 수 있도록 모든 값을 원자적으로 저장합니다.
+struct Counters {
+    count: AtomicU64,
+    total_cycles: AtomicU64,
+    min_cycles: AtomicU64,
+    max_cycles: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Counters {
+        Counters {
+            count: AtomicU64::new(0),
+            total_cycles: AtomicU64::new(0),
+            min_cycles: AtomicU64::new(u64::MAX),
+            max_cycles: AtomicU64::new(0),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_COUNTERS: Counters = Counters::new();
+static COUNTERS: [Counters; VECTORS] = [EMPTY_COUNTERS; VECTORS];
+
+/// 핸들러 진입 시각. 핸들러의 첫 줄에서 읽습니다.
+pub fn enter() -> u64 {
+    tsc::read()
+}
+
+/// `vector`의 발생 횟수를 올리고 `entry`부터 지금까지 걸린 시간을 기록합니다.
+pub fn record(vector: u8, entry: u64) {
+    let cycles = tsc::read().saturating_sub(entry);
+    let counters = &COUNTERS[usize::from(vector)];
+    counters.count.fetch_add(1, Ordering::Relaxed);
+    counters.total_cycles.fetch_add(cycles, Ordering::Relaxed);
+    counters.min_cycles.fetch_min(cycles, Ordering::Relaxed);
+    counters.max_cycles.fetch_max(cycles, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VectorStats {
+    pub count: u64,
+    pub min: Duration,
+    pub max: Duration,
+    pub average: Duration,
+}
+
+pub fn stats(vector: u8) -> VectorStats {
+    let counters = &COUNTERS[usize::from(vector)];
+    let count = counters.count.load(Ordering::Relaxed);
+    if count == 0 {
+        return VectorStats { count, min: Duration::ZERO, max: Duration::ZERO, average: Duration::ZERO };
+    }
+    VectorStats {
+        count,
+        min: tsc::cycles_to_duration(counters.min_cycles.load(Ordering::Relaxed)),
+        max: tsc::cycles_to_duration(counters.max_cycles.load(Ordering::Relaxed)),
+        average: tsc::cycles_to_duration(counters.total_cycles.load(Ordering::Relaxed) / count),
+    }
+}
+
+/// 모든 벡터의 통계를 지웁니다.
+pub fn reset() {
+    for counters in COUNTERS.iter() {
+        counters.count.store(0, Ordering::Relaxed);
+        counters.total_cycles.store(0, Ordering::Relaxed);
+        counters.min_cycles.store(u64::MAX, Ordering::Relaxed);
+        counters.max_cycles.store(0, Ordering::Relaxed);
+    }
+}
+
+const EXCEPTION_NAMES: [&str; 32] = [
+    "#DE", "#DB", "NMI", "#BP", "#OF", "#BR", "#UD", "#NM",
+    "#DF", "-", "#TS", "#NP", "#SS", "#GP", "#PF", "-",
+    "#MF", "#AC", "#MC", "#XM", "#VE", "-", "-", "-",
+    "-", "-", "-", "-", "-", "#VC", "#SX", "-",
+];
+
+struct VectorName(u8);
+
+impl fmt::Display for VectorName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let vector = self.0;
+        match vector {
+            0..=31 => write!(f, "exception {}", EXCEPTION_NAMES[usize::from(vector)]),
+            _ if (PIC_1_OFFSET..PIC_1_OFFSET + IRQ_LINES).contains(&vector) => {
+                write!(f, "IRQ{}", vector - PIC_1_OFFSET)
+            }
+            syscall::INT_VECTOR => write!(f, "int 0x80 syscall"),
+            apic::SPURIOUS_VECTOR => write!(f, "APIC spurious"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 한 번이라도 발생한 벡터만 모은 표. `println!("{}", report())`처럼 원하는 곳에 출력합니다.
+pub struct Report;
+
+pub fn report() -> Report {
+    Report
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // VGA 버퍼는 ASCII만 출력할 수 있으므로 시간은 나노초 정수로 씁니다.
+        writeln!(f, "VEC        COUNT     MIN(ns)     AVG(ns)     MAX(ns)  NAME")?;
+        for vector in 0..=u8::MAX {
+            let stats = stats(vector);
+            if stats.count == 0 {
+                continue;
+            }
+            writeln!(
+                f,
+                "{:3} {:12} {:11} {:11} {:11}  {}",
+                vector,
+                stats.count,
+                stats.min.as_nanos(),
+                stats.average.as_nanos(),
+                stats.max.as_nanos(),
+                VectorName(vector),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_record_counts_and_orders_latency() {
+    // 0x60번 벡터는 어떤 장치도 사용하지 않습니다.
+    let before = stats(0x60).count;
+    let entry = enter();
+    record(0x60, entry);
+    record(0x60, entry);
+    let stats = stats(0x60);
+    assert_eq!(stats.count, before + 2);
+    assert!(stats.min <= stats.average && stats.average <= stats.max);
+}
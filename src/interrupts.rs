@@ -1,6 +1,5 @@
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 use crate::println;
-use crate::print;
 /*
     CPU가 우리의 새로운 Interrupt Descriptor Table을 사용하기 위해서는 lidt 명령을 사용하여 로드해야 합니다.
     x86_64의 InterruptDescriptorTable 구조는 이를 위한 로드 메서드 함수를 제공합니다.
@@ -33,7 +32,17 @@ pub fn init_idt() {
 static mut는 데이터 경쟁에 매우 취약하므로 액세스할 때마다 unsafe블록이 필요합니다.
 */
 use lazy_static::lazy_static;
-use crate::gdt;
+use crate::apic::{self, ApicError};
+use crate::exceptions;
+use crate::interrupt_stats;
+use crate::monitor;
+use crate::pic;
+use crate::syscall;
+use crate::watchdog;
+use core::sync::atomic::{AtomicU16, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::set_general_handler;
+use x86_64::VirtAddr;
 
 /*
     set_cs를 사용하여 코드 세그먼트 레지스터를 다시 로드하고 load_tss를 사용하여 TSS를 로드합니다. 
@@ -41,16 +50,27 @@ use crate::gdt;
     잘못된 Selector를 로드하여 메모리 안전을 해칠 수 있기 때문입니다.
 */
 
+// 상수 제네릭 인자는 반복문으로 만들 수 없기 때문에 매크로로 라인마다 진입점을 등록합니다.
+macro_rules! set_irq_entries {
+    ($idt:ident; $($line:literal)*) => {
+        $(
+            $idt[usize::from(PIC_1_OFFSET + $line)].set_handler_fn(irq_entry::<$line>);
+        )*
+    };
+}
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        unsafe {
-            idt.double_fault.set_handler_fn(double_fault_handler)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-        }
-        idt[InterruptIndex::Timer.as_usize()]
-                .set_handler_fn(timer_interrupt_handler);
+        // 먼저 모든 벡터에 기본 핸들러를 넣고, 아래에서 처리할 벡터만 덮어씁니다.
+        set_general_handler!(&mut idt, unhandled_interrupt);
+        exceptions::register(&mut idt);
+        monitor::register(&mut idt);
+        watchdog::register(&mut idt);
+        syscall::register(&mut idt);
+        set_irq_entries!(idt; 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15);
+        idt[usize::from(apic::SPURIOUS_VECTOR)]
+                .set_handler_fn(apic_spurious_interrupt_handler);
         idt
     };
 }
@@ -60,26 +80,55 @@ pub fn init_idt() {
     IDT.load();
 }
 
-// 호출 규약과 함수를 정의합니다(x86-interrupt, breakpoint_handler)
-extern "x86-interrupt" fn breakpoint_handler( 
-    stack_frame: InterruptStackFrame)
-{
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
-}
-
 /*
-    처리기는 짧은 오류 메시지를 출력하고 예외 Stack Frame을 Dump하고, double fault handler의 오류 코드는 항상 0이므로 인쇄할 필요가 없습니다.
-    breakpoint handler의 한 가지 차이점은 double fault handler가 프로그램의 실행 순서를 변경하여 다른 명령을 실행 수 있도록 하는 것입니다.
-    그 이유는 x86_64 아키텍처가 double fault exception로부터의 반환을 허용하지 않기 때문입니다.
+    기본 핸들러
+
+    IDT에 항목이 없는 벡터로 인터럽트가 들어오면 CPU는 general protection fault를 일으키고,
+    그 핸들러도 없으면 double fault가 됩니다. 어떤 벡터가 원인이었는지는 알 수 없게 됩니다.
+    그래서 모든 벡터에 기본 핸들러를 등록해 두고, 들어온 벡터와 스택 프레임을 출력하고 횟수를 셉니다.
+    (예약된 벡터 15, 21~28, 31은 x86_64 크레이트가 설정을 허용하지 않습니다.)
 */
+const VECTORS: usize = 256;
 
-// 호출 규약과 함수를 정의합니다(x86-interrupt, double_fault_handler)
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: InterruptStackFrame, _error_code: u64) -> !
-{
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO_COUNT: AtomicU64 = AtomicU64::new(0);
+static UNHANDLED_COUNTS: [AtomicU64; VECTORS] = [ZERO_COUNT; VECTORS];
+
+fn unhandled_interrupt(stack_frame: InterruptStackFrame, vector: u8, error_code: Option<u64>) {
+    let entry = interrupt_stats::enter();
+    UNHANDLED_COUNTS[usize::from(vector)].fetch_add(1, Ordering::Relaxed);
+    match error_code {
+        Some(code) => println!("UNHANDLED INTERRUPT: vector {} (error code {:#x})\n{:#?}", vector, code, stack_frame),
+        None => println!("UNHANDLED INTERRUPT: vector {}\n{:#?}", vector, stack_frame),
+    }
+
+    // Local APIC가 보낸 인터럽트라면 EOI를 보내야 같거나 낮은 우선순위의 인터럽트가 다시 들어옵니다.
+    // int 명령으로 발생한 소프트웨어 인터럽트는 ISR에 표시되지 않으므로 EOI를 보내면 안 됩니다.
+    if let Some(local_apic) = apic::local_apic() {
+        if local_apic.in_service(vector) {
+            local_apic.end_of_interrupt();
+        }
+    }
+    interrupt_stats::record(vector, entry);
 }
 
+/// 기본 핸들러가 처리한(등록된 핸들러가 없던) 인터럽트의 수
+pub fn unhandled_count(vector: u8) -> u64 {
+    UNHANDLED_COUNTS[usize::from(vector)].load(Ordering::Relaxed)
+}
+
+/*
+    breakpoint 예외는 처음에 x86-interrupt 함수로 스택 프레임만 출력했습니다.
+
+    extern "x86-interrupt" fn breakpoint_handler(
+        stack_frame: InterruptStackFrame)
+    {
+        println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+    }
+
+    지금은 범용 레지스터까지 보여주고 바꿀 수 있도록 monitor 모듈의 진입 코드가 처리합니다.
+*/
+
 // 호출 규약과 함수를 정의합니다(x86-interrupt, timer_interrupt_handler)
 /*
     extern "x86-interrupt" fn timer_interrupt_handler(
@@ -112,40 +161,239 @@ extern "x86-interrupt" fn double_fault_handler(
     notify_end_of_interrupt는 기본 또는 보조 PIC가 인터럽트를 전송했는지 확인한 다음 명령 및 데이터 포트를 사용하여 각 컨트롤러에 EOI 신호를 전송합니다.
     보조 PIC가 인터럽트를 전송한 경우 보조 PIC가 기본 PIC의 입력 라인에 연결되어 있으므로 두 PIC에 모두 알림을 보내야 합니다.
 */
-extern "x86-interrupt" fn timer_interrupt_handler(
-    _stack_frame: InterruptStackFrame)
-{
-    print!(".");
+/*
+    이제 EOI는 IRQ 진입점(irq_entry)이 핸들러를 호출한 뒤 대신 보내므로, 타이머 핸들러는 평범한 함수가 됩니다.
+    타이머 인터럽트는 time 모듈이 등록해서 틱을 셉니다.
+*/
+
+/*
+    PIC 설정과 레지스터 접근은 pic 모듈로 옮겼습니다.
+    IRQ n은 PIC를 사용하든 APIC를 사용하든 PIC_1_OFFSET + n 벡터로 들어옵니다.
+*/
+pub use crate::pic::{PIC_1_OFFSET, PIC_2_OFFSET};
+
+/*
+    인터럽트 컨트롤러
+
+    기본적으로는 8259 PIC를 사용하고, enable_apic를 호출하면 Local APIC + I/O APIC로 전환합니다.
+    어느 쪽이든 IRQ n은 같은 벡터(PIC_1_OFFSET + n)로 들어오기 때문에 IDT와 등록된 핸들러는 그대로 사용됩니다.
+    마스크와 EOI만 현재 활성화된 컨트롤러로 보내면 됩니다.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptController {
+    Pic,
+    Apic,
+}
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+pub fn controller() -> InterruptController {
+    if apic::is_active() {
+        InterruptController::Apic
+    } else {
+        InterruptController::Pic
     }
 }
 
-use pic8259::ChainedPics;
-use spin;
+pub fn mask_irq(line: u8) {
+    match controller() {
+        InterruptController::Pic => pic::mask(line),
+        InterruptController::Apic => apic::mask_isa_irq(line),
+    }
+}
+
+pub fn unmask_irq(line: u8) {
+    match controller() {
+        InterruptController::Pic => pic::unmask(line),
+        InterruptController::Apic => apic::unmask_isa_irq(line),
+    }
+}
+
+fn end_of_interrupt(line: u8) {
+    match controller() {
+        InterruptController::Pic => pic::end_of_interrupt(line),
+        InterruptController::Apic => apic::end_of_interrupt(),
+    }
+}
+
+/// APIC로 전환합니다. 실패하면 계속 PIC를 사용합니다.
+///
+/// PIC는 비활성화하기 전에도 32~47번으로 재배치되어 있어야 합니다.
+/// 마스크된 PIC에서도 spurious interrupt는 발생할 수 있는데, 이것이 CPU 예외 벡터로 들어오면 안 되기 때문입니다.
+///
+/// # Safety
+/// 물리 메모리 전체가 `physical_memory_offset`부터 매핑되어 있어야 하고, ACPI 테이블을 읽을 수 있어야 합니다.
+pub unsafe fn enable_apic(physical_memory_offset: VirtAddr) -> Result<(), ApicError> {
+    without_interrupts(|| {
+        apic::init(physical_memory_offset, PIC_1_OFFSET)?;
+
+        // 8259의 모든 라인을 마스크해서 더 이상 인터럽트를 보내지 않게 합니다.
+        pic::disable();
+
+        // 이미 핸들러가 등록된 라인은 I/O APIC에서 다시 열어줍니다.
+        for line in 0..IRQ_LINES {
+            if IRQ_HANDLERS[usize::from(line)].load(Ordering::Acquire) != NO_HANDLER {
+                apic::unmask_isa_irq(line);
+            }
+        }
+        Ok(())
+    })
+}
+
+// APIC가 보내는 spurious interrupt에는 EOI를 보내지 않습니다.
+extern "x86-interrupt" fn apic_spurious_interrupt_handler(
+    _stack_frame: InterruptStackFrame)
+{
+    interrupt_stats::record(apic::SPURIOUS_VECTOR, interrupt_stats::enter());
+}
 
 /*
-    PIC의 기본 구성은 CPU에 0–15 범위의 Interrupt vector number를 보내기 때문에 사용할 수 없습니다.
-    현재 저 범위의 number는 이미 CPU exception에 의해 점유되고 있습니다.
-    예를 들어 숫자 8은 double fault에 해당합니다.
-    이 겹치는 문제를 해결하려면 PIC 인터럽트를 다른 번호로 다시 매핑해야 합니다.
-    실제 범위는 예외와 겹치지 않는 한 중요하지 않지만 일반적으로 32-47 범위가 선택됩니다.
-    이는 32개의 예외 슬롯 이후 첫 번째 여유 번호이기 때문입니다.
+    IRQ 핸들러 등록
+
+    IDT에는 16개의 레거시 IRQ 각각에 대한 진입점(irq_entry::<LINE>)을 미리 등록해 둡니다.
+    진입점은 IRQ_HANDLERS 테이블에서 드라이버가 등록한 함수를 찾아 호출한 뒤 인터럽트 컨트롤러에 EOI를 보냅니다.
+    덕분에 드라이버는 이 파일이나 InterruptIndex를 고치지 않고 register_irq만으로 인터럽트를 받을 수 있습니다.
+    함수 포인터는 usize로 바꿔 원자적으로 저장하므로, 핸들러 안에서도 잠금 없이 읽을 수 있습니다.
 */
+pub const IRQ_LINES: u8 = 16;
+
+pub type IrqHandler = fn();
 
-pub const PIC_1_OFFSET: u8 = 32;
-pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    InvalidLine(u8),
+    AlreadyRegistered(u8),
+    NotRegistered(u8),
+}
+
+const NO_HANDLER: usize = 0;
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: AtomicUsize = AtomicUsize::new(NO_HANDLER);
+static IRQ_HANDLERS: [AtomicUsize; IRQ_LINES as usize] = [EMPTY_SLOT; IRQ_LINES as usize];
+
+fn irq_slot(line: u8) -> Result<&'static AtomicUsize, IrqError> {
+    IRQ_HANDLERS.get(usize::from(line)).ok_or(IrqError::InvalidLine(line))
+}
+
+/// IRQ 라인에 핸들러를 등록하고 라인의 마스크를 해제합니다. 한 라인에는 하나의 핸들러만 등록할 수 있습니다.
+pub fn register_irq(line: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    irq_slot(line)?
+        .compare_exchange(NO_HANDLER, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+        .map_err(|_| IrqError::AlreadyRegistered(line))?;
+    unmask_irq(line);
+    Ok(())
+}
+
+/// IRQ 라인을 마스크하고 등록되어 있던 핸들러를 돌려줍니다.
+pub fn unregister_irq(line: u8) -> Result<IrqHandler, IrqError> {
+    let slot = irq_slot(line)?;
+    mask_irq(line);
+    match slot.swap(NO_HANDLER, Ordering::AcqRel) {
+        NO_HANDLER => Err(IrqError::NotRegistered(line)),
+        handler => Ok(unsafe { core::mem::transmute::<usize, IrqHandler>(handler) }),
+    }
+}
 
 /*
-    위에서 언급한 바와 같이 사진의 오프셋을 32-47 범위로 설정합니다.
-    Chained Pics 구조를 Mutex로 감싸면 다음 단계에서 필요한 (잠금 방법을 통해) 안전한 가변 액세스를 얻을 수 있습니다.
-    그러나 잘못된 오프셋으로 인해 정의되지 않은 동작이 발생할 수 있으므로 ChainedPics::new 함수는 안전하지 않습니다.
+    우선순위와 중첩 인터럽트
+
+    8259는 IRQ 번호가 작을수록 우선순위가 높고(보조 PIC의 IRQ8~15는 IRQ2 자리에 들어갑니다),
+    처리 중인 인터럽트보다 우선순위가 높은 인터럽트는 EOI 전에도 CPU에 보냅니다.
+    하지만 CPU는 인터럽트 게이트로 들어오면서 IF를 끄기 때문에, 핸들러가 끝날 때까지 어떤 인터럽트도 받지 않습니다.
+
+    set_irq_nesting으로 중첩을 허용한 라인은 진입점이 다음 순서로 처리합니다.
+    1. 우선순위가 같거나 낮은 라인을 PIC의 우선순위 마스크로 막습니다.
+    2. EOI를 먼저 보내고 인터럽트를 켭니다.
+    3. 핸들러를 실행한 뒤 인터럽트를 끄고 우선순위 마스크를 되돌립니다.
+    그러면 느린 핸들러(디스크, 시리얼 등)가 실행되는 동안에도 타이머처럼 우선순위가 높은 인터럽트가 끼어들 수 있습니다.
+    우선순위는 0이 가장 높고, 기본값은 8259의 고정 우선순위와 같습니다. APIC를 사용할 때는 중첩하지 않습니다.
 */
+pub type IrqPriority = u8;
+
+const fn default_priority(line: u8) -> IrqPriority {
+    match line {
+        0..=2 => line,
+        3..=7 => line + 7,
+        _ => line - 6,
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const DEFAULT_PRIORITY: AtomicU8 = AtomicU8::new(0);
+static IRQ_PRIORITIES: [AtomicU8; IRQ_LINES as usize] = {
+    let mut priorities = [DEFAULT_PRIORITY; IRQ_LINES as usize];
+    let mut line = 0;
+    while line < IRQ_LINES {
+        priorities[line as usize] = AtomicU8::new(default_priority(line));
+        line += 1;
+    }
+    priorities
+};
+static IRQ_NESTING: AtomicU16 = AtomicU16::new(0);
 
-pub static PICS: spin::Mutex<ChainedPics> =
-    spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+pub fn set_irq_priority(line: u8, priority: IrqPriority) -> Result<(), IrqError> {
+    irq_slot(line)?;
+    IRQ_PRIORITIES[usize::from(line)].store(priority, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn irq_priority(line: u8) -> Result<IrqPriority, IrqError> {
+    irq_slot(line)?;
+    Ok(IRQ_PRIORITIES[usize::from(line)].load(Ordering::Relaxed))
+}
+
+/// 핸들러가 실행되는 동안 우선순위가 더 높은 IRQ가 끼어들 수 있게 할지 정합니다.
+pub fn set_irq_nesting(line: u8, allowed: bool) -> Result<(), IrqError> {
+    irq_slot(line)?;
+    if allowed {
+        IRQ_NESTING.fetch_or(1 << line, Ordering::Relaxed);
+    } else {
+        IRQ_NESTING.fetch_and(!(1 << line), Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+// `line`보다 우선순위가 같거나 낮은 라인들. cascade 라인을 막으면 보조 PIC 전체가 막히므로 제외합니다.
+fn lower_priority_lines(line: u8) -> u16 {
+    let priority = IRQ_PRIORITIES[usize::from(line)].load(Ordering::Relaxed);
+    (0..IRQ_LINES)
+        .filter(|&other| other != pic::CASCADE_IRQ)
+        .filter(|&other| IRQ_PRIORITIES[usize::from(other)].load(Ordering::Relaxed) >= priority)
+        .fold(0, |mask, other| mask | 1 << other)
+}
+
+extern "x86-interrupt" fn irq_entry<const LINE: u8>(_stack_frame: InterruptStackFrame) {
+    let entry = interrupt_stats::enter();
+    // spurious IRQ는 장치가 보낸 것이 아니므로 핸들러를 호출하지 않습니다. 필요한 EOI는 check_spurious가 보냅니다.
+    let pic_mode = controller() == InterruptController::Pic;
+    if pic_mode && pic::check_spurious(LINE) {
+        interrupt_stats::record(PIC_1_OFFSET + LINE, entry);
+        return;
+    }
+
+    let handler = match IRQ_HANDLERS[usize::from(LINE)].load(Ordering::Acquire) {
+        NO_HANDLER => None,
+        handler => Some(unsafe { core::mem::transmute::<usize, IrqHandler>(handler) }),
+    };
+
+    if pic_mode && IRQ_NESTING.load(Ordering::Relaxed) & (1 << LINE) != 0 {
+        let previous = pic::set_priority_mask(pic::priority_mask() | lower_priority_lines(LINE));
+        end_of_interrupt(LINE);
+        interrupt_stats::record(PIC_1_OFFSET + LINE, entry);
+
+        x86_64::instructions::interrupts::enable();
+        if let Some(handler) = handler {
+            handler();
+        }
+        x86_64::instructions::interrupts::disable();
+        pic::set_priority_mask(previous);
+        return;
+    }
+
+    if let Some(handler) = handler {
+        handler();
+    }
+    end_of_interrupt(LINE);
+    interrupt_stats::record(PIC_1_OFFSET + LINE, entry);
+}
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
@@ -158,8 +406,9 @@ impl InterruptIndex {
         self as u8
     }
 
-    fn as_usize(self) -> usize {
-        usize::from(self.as_u8())
+    /// 인터럽트 벡터에 해당하는 PIC의 IRQ 라인 번호
+    pub fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
     }
 }
 
@@ -167,4 +416,29 @@ impl InterruptIndex {
 fn test_breakpoint_exception() {
     // invoke a breakpoint exception
     x86_64::instructions::interrupts::int3();
-}
\ No newline at end of file
+}
+#[test_case]
+fn test_unhandled_vector_is_counted() {
+    // 0x50번 벡터에는 기본 핸들러만 등록되어 있습니다.
+    let before = unhandled_count(0x50);
+    unsafe { core::arch::asm!("int 0x50", options(nomem, nostack)) };
+    assert_eq!(unhandled_count(0x50), before + 1);
+}
+#[test_case]
+fn test_register_and_unregister_irq() {
+    fn handler() {}
+    // IRQ5는 QEMU 기본 구성에서 사용되지 않는 라인입니다.
+    assert_eq!(register_irq(5, handler), Ok(()));
+    assert_eq!(register_irq(5, handler), Err(IrqError::AlreadyRegistered(5)));
+    assert!(unregister_irq(5).is_ok());
+    assert!(unregister_irq(5).is_err());
+    assert_eq!(register_irq(IRQ_LINES, handler), Err(IrqError::InvalidLine(IRQ_LINES)));
+}
+#[test_case]
+fn test_default_irq_priorities() {
+    // IRQ1(키보드)을 처리하는 동안에는 IRQ0(타이머)만 끼어들 수 있습니다.
+    assert_eq!(lower_priority_lines(1), 0xFFFA);
+    // 보조 PIC의 라인은 주 PIC의 IRQ3~7보다 우선순위가 높습니다.
+    assert!(irq_priority(8).unwrap() < irq_priority(3).unwrap());
+    assert_eq!(set_irq_priority(IRQ_LINES, 0), Err(IrqError::InvalidLine(IRQ_LINES)));
+}
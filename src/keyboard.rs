@@ -0,0 +1,418 @@
+//! PS/2 키보드 드라이버
+//!
+//! 키보드 컨트롤러는 키를 누르거나 뗄 때마다 IRQ1을 발생시키고, 데이터 포트(0x60)에 scancode를 남깁니다.
+//! scancode는 키의 위치만 알려주므로 어떤 키인지, 어떤 문자인지는 드라이버가 해석해야 합니다.
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use crate::input::{self, InputEvent};
+use crate::interrupts::{self, IrqError};
+use crate::workqueue;
+
+const DATA_PORT: u16 = 0x60;
+
+/*
+    Scancode Set
+
+    키보드가 보내는 scancode의 형식은 세 가지(set 1, 2, 3)가 있습니다.
+    대부분의 키보드는 set 2를 사용하지만, PS/2 컨트롤러는 기본적으로 이를 IBM XT 시절의 set 1로 변환해서 전달합니다.
+    컨트롤러의 변환 기능을 끈 경우에만 set 2를 직접 받게 됩니다.
+
+    Set 1: 키를 누르면 make code, 떼면 make code | 0x80 (break code)
+    Set 2: 키를 누르면 make code, 떼면 0xF0 뒤에 make code
+    두 형식 모두 나중에 추가된 키는 0xE0 접두사가 붙고, Pause 키는 0xE1로 시작하는 긴 시퀀스를 보냅니다.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    Down,
+    Up,
+}
+
+/// US 배열 키보드 기준의 물리적인 키
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCode {
+    Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Backquote, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0, Minus, Equals, Backspace,
+    Tab, Q, W, E, R, T, Y, U, I, O, P, LeftBracket, RightBracket, Backslash,
+    CapsLock, A, S, D, F, G, H, J, K, L, Semicolon, Quote, Enter,
+    LeftShift, Z, X, C, V, B, N, M, Comma, Period, Slash, RightShift,
+    LeftCtrl, LeftGui, LeftAlt, Space, RightAlt, RightGui, Menu, RightCtrl,
+    Insert, Home, PageUp, Delete, End, PageDown,
+    ArrowUp, ArrowLeft, ArrowDown, ArrowRight,
+    NumLock, ScrollLock, KeypadSlash, KeypadStar, KeypadMinus, KeypadPlus, KeypadEnter, KeypadPeriod,
+    Keypad0, Keypad1, Keypad2, Keypad3, Keypad4, Keypad5, Keypad6, Keypad7, Keypad8, Keypad9,
+}
+
+/// 수식 키의 현재 상태
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_ctrl: bool,
+    pub right_ctrl: bool,
+    pub left_alt: bool,
+    pub right_alt: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
+
+impl Modifiers {
+    pub fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.left_ctrl || self.right_ctrl
+    }
+
+    pub fn alt(&self) -> bool {
+        self.left_alt || self.right_alt
+    }
+
+    fn update(&mut self, code: KeyCode, state: KeyState) {
+        let down = state == KeyState::Down;
+        match code {
+            KeyCode::LeftShift => self.left_shift = down,
+            KeyCode::RightShift => self.right_shift = down,
+            KeyCode::LeftCtrl => self.left_ctrl = down,
+            KeyCode::RightCtrl => self.right_ctrl = down,
+            KeyCode::LeftAlt => self.left_alt = down,
+            KeyCode::RightAlt => self.right_alt = down,
+            // 잠금 키는 누를 때마다 상태가 바뀝니다.
+            KeyCode::CapsLock if down => self.caps_lock = !self.caps_lock,
+            KeyCode::NumLock if down => self.num_lock = !self.num_lock,
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub state: KeyState,
+    /// 키를 눌렀을 때 입력되는 문자. 키를 뗄 때와 문자가 없는 키는 `None`입니다.
+    pub ch: Option<char>,
+    pub modifiers: Modifiers,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecodeState {
+    Start,
+    Extended,
+    Release,
+    ExtendedRelease,
+    // Pause 키 시퀀스에서 남은 바이트 수
+    Pause(u8),
+}
+
+/// scancode 바이트를 하나씩 받아 키 이벤트로 바꿉니다.
+pub struct Decoder {
+    set: ScancodeSet,
+    state: DecodeState,
+    modifiers: Modifiers,
+}
+
+impl Decoder {
+    pub const fn new(set: ScancodeSet) -> Decoder {
+        Decoder {
+            set,
+            state: DecodeState::Start,
+            modifiers: Modifiers {
+                left_shift: false,
+                right_shift: false,
+                left_ctrl: false,
+                right_ctrl: false,
+                left_alt: false,
+                right_alt: false,
+                caps_lock: false,
+                num_lock: false,
+            },
+        }
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    pub fn scancode_set(&self) -> ScancodeSet {
+        self.set
+    }
+
+    pub fn add_byte(&mut self, byte: u8) -> Option<KeyEvent> {
+        let (code, state) = match self.set {
+            ScancodeSet::Set1 => self.decode_set1(byte)?,
+            ScancodeSet::Set2 => self.decode_set2(byte)?,
+        };
+        self.modifiers.update(code, state);
+
+        let ch = match state {
+            KeyState::Down => to_char(code, &self.modifiers),
+            KeyState::Up => None,
+        };
+        Some(KeyEvent { code, state, ch, modifiers: self.modifiers })
+    }
+
+    fn decode_set1(&mut self, byte: u8) -> Option<(KeyCode, KeyState)> {
+        let state = if byte & 0x80 == 0 { KeyState::Down } else { KeyState::Up };
+        match (self.state, byte) {
+            (DecodeState::Pause(remaining), _) => {
+                self.state = if remaining > 1 { DecodeState::Pause(remaining - 1) } else { DecodeState::Start };
+                None
+            }
+            // 키보드 명령에 대한 응답(ACK, Resend)과 오류 값
+            (_, 0x00) | (_, 0xFA) | (_, 0xFE) | (_, 0xFF) => None,
+            (DecodeState::Start, 0xE0) => {
+                self.state = DecodeState::Extended;
+                None
+            }
+            (DecodeState::Start, 0xE1) => {
+                // E1 1D 45 E1 9D C5
+                self.state = DecodeState::Pause(5);
+                None
+            }
+            (DecodeState::Extended, _) => {
+                self.state = DecodeState::Start;
+                set1_extended_key(byte & 0x7F).map(|code| (code, state))
+            }
+            _ => {
+                self.state = DecodeState::Start;
+                set1_key(byte & 0x7F).map(|code| (code, state))
+            }
+        }
+    }
+
+    fn decode_set2(&mut self, byte: u8) -> Option<(KeyCode, KeyState)> {
+        match (self.state, byte) {
+            (DecodeState::Pause(remaining), _) => {
+                self.state = if remaining > 1 { DecodeState::Pause(remaining - 1) } else { DecodeState::Start };
+                None
+            }
+            // 키보드 명령에 대한 응답(ACK, Resend, Echo), 자체 테스트 통과(0xAA)와 오류 값
+            (_, 0x00) | (_, 0xAA) | (_, 0xEE) | (_, 0xFA) | (_, 0xFE) | (_, 0xFF) => None,
+            (DecodeState::Start, 0xE0) => {
+                self.state = DecodeState::Extended;
+                None
+            }
+            (DecodeState::Start, 0xE1) => {
+                // E1 14 77 E1 F0 14 F0 77
+                self.state = DecodeState::Pause(7);
+                None
+            }
+            (DecodeState::Start, 0xF0) => {
+                self.state = DecodeState::Release;
+                None
+            }
+            (DecodeState::Extended, 0xF0) => {
+                self.state = DecodeState::ExtendedRelease;
+                None
+            }
+            (DecodeState::Extended, _) => {
+                self.state = DecodeState::Start;
+                set2_extended_key(byte).map(|code| (code, KeyState::Down))
+            }
+            (DecodeState::ExtendedRelease, _) => {
+                self.state = DecodeState::Start;
+                set2_extended_key(byte).map(|code| (code, KeyState::Up))
+            }
+            (DecodeState::Release, _) => {
+                self.state = DecodeState::Start;
+                set2_key(byte).map(|code| (code, KeyState::Up))
+            }
+            _ => set2_key(byte).map(|code| (code, KeyState::Down)),
+        }
+    }
+}
+
+fn set1_key(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x01 => Escape,
+        0x02 => Key1, 0x03 => Key2, 0x04 => Key3, 0x05 => Key4, 0x06 => Key5,
+        0x07 => Key6, 0x08 => Key7, 0x09 => Key8, 0x0A => Key9, 0x0B => Key0,
+        0x0C => Minus, 0x0D => Equals, 0x0E => Backspace, 0x0F => Tab,
+        0x10 => Q, 0x11 => W, 0x12 => E, 0x13 => R, 0x14 => T,
+        0x15 => Y, 0x16 => U, 0x17 => I, 0x18 => O, 0x19 => P,
+        0x1A => LeftBracket, 0x1B => RightBracket, 0x1C => Enter, 0x1D => LeftCtrl,
+        0x1E => A, 0x1F => S, 0x20 => D, 0x21 => F, 0x22 => G,
+        0x23 => H, 0x24 => J, 0x25 => K, 0x26 => L,
+        0x27 => Semicolon, 0x28 => Quote, 0x29 => Backquote, 0x2A => LeftShift, 0x2B => Backslash,
+        0x2C => Z, 0x2D => X, 0x2E => C, 0x2F => V, 0x30 => B, 0x31 => N, 0x32 => M,
+        0x33 => Comma, 0x34 => Period, 0x35 => Slash, 0x36 => RightShift,
+        0x37 => KeypadStar, 0x38 => LeftAlt, 0x39 => Space, 0x3A => CapsLock,
+        0x3B => F1, 0x3C => F2, 0x3D => F3, 0x3E => F4, 0x3F => F5,
+        0x40 => F6, 0x41 => F7, 0x42 => F8, 0x43 => F9, 0x44 => F10,
+        0x45 => NumLock, 0x46 => ScrollLock,
+        0x47 => Keypad7, 0x48 => Keypad8, 0x49 => Keypad9, 0x4A => KeypadMinus,
+        0x4B => Keypad4, 0x4C => Keypad5, 0x4D => Keypad6, 0x4E => KeypadPlus,
+        0x4F => Keypad1, 0x50 => Keypad2, 0x51 => Keypad3, 0x52 => Keypad0, 0x53 => KeypadPeriod,
+        0x57 => F11, 0x58 => F12,
+        _ => return None,
+    })
+}
+
+// 0xE0 접두사가 붙은 키. Print Screen 등이 함께 보내는 가짜 Shift(0x2A, 0x36)는 무시합니다.
+fn set1_extended_key(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x1C => KeypadEnter, 0x1D => RightCtrl, 0x35 => KeypadSlash, 0x38 => RightAlt,
+        0x47 => Home, 0x48 => ArrowUp, 0x49 => PageUp, 0x4B => ArrowLeft, 0x4D => ArrowRight,
+        0x4F => End, 0x50 => ArrowDown, 0x51 => PageDown, 0x52 => Insert, 0x53 => Delete,
+        0x5B => LeftGui, 0x5C => RightGui, 0x5D => Menu,
+        _ => return None,
+    })
+}
+
+fn set2_key(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x76 => Escape,
+        0x05 => F1, 0x06 => F2, 0x04 => F3, 0x0C => F4, 0x03 => F5, 0x0B => F6,
+        0x83 => F7, 0x0A => F8, 0x01 => F9, 0x09 => F10, 0x78 => F11, 0x07 => F12,
+        0x0E => Backquote,
+        0x16 => Key1, 0x1E => Key2, 0x26 => Key3, 0x25 => Key4, 0x2E => Key5,
+        0x36 => Key6, 0x3D => Key7, 0x3E => Key8, 0x46 => Key9, 0x45 => Key0,
+        0x4E => Minus, 0x55 => Equals, 0x66 => Backspace, 0x0D => Tab,
+        0x15 => Q, 0x1D => W, 0x24 => E, 0x2D => R, 0x2C => T,
+        0x35 => Y, 0x3C => U, 0x43 => I, 0x44 => O, 0x4D => P,
+        0x54 => LeftBracket, 0x5B => RightBracket, 0x5D => Backslash, 0x58 => CapsLock,
+        0x1C => A, 0x1B => S, 0x23 => D, 0x2B => F, 0x34 => G,
+        0x33 => H, 0x3B => J, 0x42 => K, 0x4B => L,
+        0x4C => Semicolon, 0x52 => Quote, 0x5A => Enter, 0x12 => LeftShift,
+        0x1A => Z, 0x22 => X, 0x21 => C, 0x2A => V, 0x32 => B, 0x31 => N, 0x3A => M,
+        0x41 => Comma, 0x49 => Period, 0x4A => Slash, 0x59 => RightShift,
+        0x14 => LeftCtrl, 0x11 => LeftAlt, 0x29 => Space,
+        0x77 => NumLock, 0x7E => ScrollLock,
+        0x7C => KeypadStar, 0x7B => KeypadMinus, 0x79 => KeypadPlus, 0x71 => KeypadPeriod,
+        0x70 => Keypad0, 0x69 => Keypad1, 0x72 => Keypad2, 0x7A => Keypad3, 0x6B => Keypad4,
+        0x73 => Keypad5, 0x74 => Keypad6, 0x6C => Keypad7, 0x75 => Keypad8, 0x7D => Keypad9,
+        _ => return None,
+    })
+}
+
+// 0xE0 접두사가 붙은 키. 가짜 Shift(0x12, 0x59)는 무시합니다.
+fn set2_extended_key(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x11 => RightAlt, 0x14 => RightCtrl, 0x1F => LeftGui, 0x27 => RightGui, 0x2F => Menu,
+        0x4A => KeypadSlash, 0x5A => KeypadEnter,
+        0x69 => End, 0x6B => ArrowLeft, 0x6C => Home, 0x70 => Insert, 0x71 => Delete,
+        0x72 => ArrowDown, 0x74 => ArrowRight, 0x75 => ArrowUp, 0x7A => PageDown, 0x7D => PageUp,
+        _ => return None,
+    })
+}
+
+// US 배열 기준으로 키와 수식 키 상태를 문자로 바꿉니다.
+fn to_char(code: KeyCode, modifiers: &Modifiers) -> Option<char> {
+    use KeyCode::*;
+    let shift = modifiers.shift();
+
+    let letter = |lower: char| {
+        // Caps Lock은 문자 키에만 적용되고, Shift와 함께 누르면 다시 소문자가 됩니다.
+        if shift != modifiers.caps_lock { lower.to_ascii_uppercase() } else { lower }
+    };
+    let pair = |normal: char, shifted: char| if shift { shifted } else { normal };
+    let keypad = |digit: char| if modifiers.num_lock { Some(digit) } else { None };
+
+    match code {
+        A => Some(letter('a')), B => Some(letter('b')), C => Some(letter('c')), D => Some(letter('d')),
+        E => Some(letter('e')), F => Some(letter('f')), G => Some(letter('g')), H => Some(letter('h')),
+        I => Some(letter('i')), J => Some(letter('j')), K => Some(letter('k')), L => Some(letter('l')),
+        M => Some(letter('m')), N => Some(letter('n')), O => Some(letter('o')), P => Some(letter('p')),
+        Q => Some(letter('q')), R => Some(letter('r')), S => Some(letter('s')), T => Some(letter('t')),
+        U => Some(letter('u')), V => Some(letter('v')), W => Some(letter('w')), X => Some(letter('x')),
+        Y => Some(letter('y')), Z => Some(letter('z')),
+        Key1 => Some(pair('1', '!')), Key2 => Some(pair('2', '@')), Key3 => Some(pair('3', '#')),
+        Key4 => Some(pair('4', '$')), Key5 => Some(pair('5', '%')), Key6 => Some(pair('6', '^')),
+        Key7 => Some(pair('7', '&')), Key8 => Some(pair('8', '*')), Key9 => Some(pair('9', '(')),
+        Key0 => Some(pair('0', ')')),
+        Backquote => Some(pair('`', '~')), Minus => Some(pair('-', '_')), Equals => Some(pair('=', '+')),
+        LeftBracket => Some(pair('[', '{')), RightBracket => Some(pair(']', '}')),
+        Backslash => Some(pair('\\', '|')), Semicolon => Some(pair(';', ':')),
+        Quote => Some(pair('\'', '"')), Comma => Some(pair(',', '<')),
+        Period => Some(pair('.', '>')), Slash => Some(pair('/', '?')),
+        Space => Some(' '), Tab => Some('\t'), Enter | KeypadEnter => Some('\n'),
+        Backspace => Some('\x08'), Escape => Some('\x1b'), Delete => Some('\x7f'),
+        KeypadSlash => Some('/'), KeypadStar => Some('*'), KeypadMinus => Some('-'), KeypadPlus => Some('+'),
+        Keypad0 => keypad('0'), Keypad1 => keypad('1'), Keypad2 => keypad('2'), Keypad3 => keypad('3'),
+        Keypad4 => keypad('4'), Keypad5 => keypad('5'), Keypad6 => keypad('6'), Keypad7 => keypad('7'),
+        Keypad8 => keypad('8'), Keypad9 => keypad('9'), KeypadPeriod => keypad('.'),
+        _ => None,
+    }
+}
+
+static DECODER: Mutex<Decoder> = Mutex::new(Decoder::new(ScancodeSet::Set1));
+
+/// 컨트롤러의 변환 기능을 끄고 set 2를 직접 받을 때 사용합니다.
+pub fn set_scancode_set(set: ScancodeSet) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        *DECODER.lock() = Decoder::new(set);
+    });
+}
+
+pub fn scancode_set() -> ScancodeSet {
+    x86_64::instructions::interrupts::without_interrupts(|| DECODER.lock().scancode_set())
+}
+
+const IRQ: u8 = 1;
+
+/// IRQ1에 키보드 핸들러를 등록합니다.
+pub fn init() -> Result<(), IrqError> {
+    interrupts::register_irq(IRQ, handle_interrupt)
+}
+
+/*
+    키보드 컨트롤러는 데이터 포트(0x60)에서 scancode를 읽기 전까지 다음 인터럽트를 보내지 않습니다.
+    핸들러는 scancode만 읽고, 해석은 작업 큐에서 인터럽트가 켜진 상태로 합니다.
+*/
+fn handle_interrupt() {
+    let mut port = Port::new(DATA_PORT);
+    let scancode: u8 = unsafe { port.read() };
+
+    // 큐가 가득 차면 키 입력 하나를 잃지만, 핸들러 안에서는 할 수 있는 일이 없습니다.
+    let _ = workqueue::schedule(decode_scancode, usize::from(scancode));
+}
+
+// scancode는 도착한 순서대로 작업 큐에서 하나씩 해석되므로 여러 바이트로 된 scancode도 순서가 유지됩니다.
+fn decode_scancode(scancode: usize) {
+    if let Some(event) = DECODER.lock().add_byte(scancode as u8) {
+        input::push(InputEvent::Key(event));
+    }
+}
+
+#[test_case]
+fn test_decode_set1_shifted_letter() {
+    let mut decoder = Decoder::new(ScancodeSet::Set1);
+    assert_eq!(decoder.add_byte(0x2A).map(|e| e.code), Some(KeyCode::LeftShift));
+    let event = decoder.add_byte(0x1E).unwrap();
+    assert_eq!((event.code, event.state, event.ch), (KeyCode::A, KeyState::Down, Some('A')));
+    decoder.add_byte(0xAA);
+    let event = decoder.add_byte(0x9E).unwrap();
+    assert_eq!((event.code, event.state, event.ch), (KeyCode::A, KeyState::Up, None));
+    assert!(!event.modifiers.shift());
+}
+
+#[test_case]
+fn test_decode_set1_extended_key() {
+    let mut decoder = Decoder::new(ScancodeSet::Set1);
+    assert_eq!(decoder.add_byte(0xE0), None);
+    let event = decoder.add_byte(0xC8).unwrap();
+    assert_eq!((event.code, event.state), (KeyCode::ArrowUp, KeyState::Up));
+}
+
+#[test_case]
+fn test_decode_set2_release_and_caps_lock() {
+    let mut decoder = Decoder::new(ScancodeSet::Set2);
+    decoder.add_byte(0x58);
+    decoder.add_byte(0xF0);
+    decoder.add_byte(0x58);
+    assert!(decoder.modifiers().caps_lock);
+    assert_eq!(decoder.add_byte(0x1C).and_then(|e| e.ch), Some('A'));
+    assert_eq!(decoder.add_byte(0xF0), None);
+    let event = decoder.add_byte(0x1C).unwrap();
+    assert_eq!((event.code, event.state), (KeyCode::A, KeyState::Up));
+}
@@ -4,13 +4,44 @@
 /*  오류가 발생하는 이유는 x86-interrupt 호출 규칙이 여전히 불안정하기 때문에 발생합니다.
     따라서 lib.rs 상단에 추가하여 명시적으로 활성화 시켜야 합니다. */
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+extern crate alloc;
+
 pub mod serial;
 pub mod vga_buffer;
 pub mod interrupts;
+pub mod pic;
+pub mod exceptions;
+pub mod interrupt_stats;
 pub mod gdt;
+pub mod stack;
+pub mod acpi;
+pub mod apic;
+pub mod power;
+pub mod ring_buffer;
+pub mod input;
+pub mod keyboard;
+pub mod ps2;
+pub mod mouse;
+pub mod monitor;
+pub mod pit;
+pub mod time;
+pub mod timer;
+pub mod rtc;
+pub mod hpet;
+pub mod tsc;
+pub mod workqueue;
+pub mod backtrace;
+pub mod watchdog;
+pub mod usermode;
+pub mod syscall;
+pub mod frame_allocator;
+pub mod memory;
+pub mod allocator;
+pub mod test_support;
 
 use core::panic::PanicInfo;
 
@@ -24,8 +55,9 @@ where
 {
     fn run(&self) {
         serial_print!("{}...\t", core::any::type_name::<T>());
+        let start = tsc::Instant::now();
         self();
-        serial_println!("[ok]");
+        serial_println!("[ok] ({:?})", start.elapsed());
     }
 }
 
@@ -34,6 +66,8 @@ pub fn test_runner(tests: &[&dyn Testable]) {
     for test in tests {
         test.run();
     }
+    // 스택 크기를 정할 때 참고하도록 테스트가 끝난 뒤의 최대 사용량을 남깁니다.
+    serial_println!("\n{}", stack::report());
     exit_qemu(QemuExitCode::Success);
 }
 
@@ -41,16 +75,28 @@ pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
     exit_qemu(QemuExitCode::Failed);
-    loop {}
 }
 
+use bootloader::BootInfo;
+#[cfg(test)]
+use bootloader::entry_point;
+
+#[cfg(test)]
+entry_point!(test_kernel_main);
+
 /// Entry point for `cargo test`
 #[cfg(test)]
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
     init();      // new
+    // main과 같은 순서로 초기화해서 HPET, APIC 위에서도 테스트를 돌립니다.
+    init_memory(boot_info);
     test_main();
-    loop {}
+    hlt_loop();
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
+    allocator::alloc_error(layout)
 }
 
 #[cfg(test)]
@@ -66,18 +112,66 @@ pub enum QemuExitCode {
     Failed = 0x11,
 }
 
-pub fn exit_qemu(exit_code: QemuExitCode) {
+/*
+    isa-debug-exit 장치가 0xf4에 연결되어 있으면 QEMU가 (exit_code << 1) | 1 값으로 종료됩니다.
+    장치가 없는 경우(일반 QEMU 실행, 실제 하드웨어)에는 포트 쓰기가 무시되므로 일반적인 전원 끄기로 넘어갑니다.
+*/
+pub fn exit_qemu(exit_code: QemuExitCode) -> ! {
     use x86_64::instructions::port::Port;
 
     unsafe {
         let mut port = Port::new(0xf4);
         port.write(exit_code as u32);
     }
+    power::shutdown()
 }
 
 pub fn init() {
+    stack::init();
     gdt::init();
+    syscall::init();
     interrupts::init_idt();
-    unsafe { interrupts::PICS.lock().initialize() };
+    pic::init();
+    time::init(time::DEFAULT_FREQUENCY).expect("timer IRQ is already registered");
+    tsc::calibrate();
+    keyboard::init().expect("keyboard IRQ is already registered");
+    if let Err(err) = mouse::init() {
+        println!("PS/2 mouse not available: {:?}", err);
+    }
     x86_64::instructions::interrupts::enable();
-}
\ No newline at end of file
+}
+
+/*
+    물리 메모리 매핑이 있어야 하는 초기화. `init` 다음에 부트로더가 넘겨준 BootInfo로 한 번 호출합니다.
+    ACPI 테이블과 HPET, APIC의 레지스터는 물리 주소로만 알 수 있으므로 여기서 찾습니다.
+    없는 장치는 건너뛰고 PIT와 8259 PIC를 계속 사용합니다.
+    IST 스택의 guard page 매핑도 여기서 지우므로, 그 전에는 넘친 스택이 이웃한 스택을 덮어씁니다.
+*/
+pub fn init_memory(boot_info: &'static BootInfo) {
+    unsafe { memory::init(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
+    let offset = memory::physical_memory_offset().expect("memory::init sets the offset");
+
+    if let Err(err) = unsafe { acpi::init(offset) } {
+        println!("ACPI not available: {:?}", err);
+        return;
+    }
+    match unsafe { hpet::init(offset) } {
+        // PIT로 재었던 TSC 주파수를 더 정확한 HPET로 다시 잽니다.
+        Ok(()) => {
+            tsc::calibrate();
+        }
+        Err(err) => println!("HPET not available: {:?}", err),
+    }
+    if let Err(err) = unsafe { interrupts::enable_apic(offset) } {
+        println!("APIC not available: {:?}", err);
+    }
+}
+
+// 다음 인터럽트가 올 때까지 CPU를 쉬게 합니다. 빈 loop {}와 달리 CPU 시간을 소모하지 않습니다.
+// 깨어날 때마다 작업 큐에 쌓인 일을 처리합니다.
+pub fn hlt_loop() -> ! {
+    loop {
+        workqueue::idle();
+    }
+}
@@ -5,13 +5,22 @@
 #![reexport_test_harness_main = "test_main"]
 
 use core::panic::PanicInfo;
-use blog_os::println;
+use bootloader::{entry_point, BootInfo};
+use blog_os::{print, println};
+use blog_os::input::{self, InputEvent};
+use blog_os::{allocator, frame_allocator, interrupt_stats, monitor, watchdog};
+use blog_os::keyboard::{KeyCode, KeyEvent, KeyState};
 
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
+// 부트로더가 메모리 맵을 담은 BootInfo를 넘겨주며 호출합니다. 인자의 타입은 entry_point! 매크로가 확인합니다.
+entry_point!(kernel_main);
+
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
     println!("Hello World{}", "!");
 
     blog_os::init();
+    blog_os::init_memory(boot_info);
+    println!("physical memory: {}", frame_allocator::stats());
+    println!("heap: {} KiB, {} allocator", allocator::HEAP_SIZE / 1024, allocator::allocator_name());
 
     // fn stack_overflow() {
     //     // for each recursion, the return address is pushed
@@ -26,7 +35,27 @@ pub extern "C" fn _start() -> ! {
     test_main();
 
     println!("It did not crash!");
-    loop {}
+
+    // 2초 동안 타이머 틱이 멈추면 시리얼 포트로 레지스터와 backtrace를 출력합니다.
+    if let Err(error) = watchdog::start(core::time::Duration::from_secs(2)) {
+        println!("watchdog not started: {:?}", error);
+    }
+
+    // 키보드로 입력한 문자를 화면에 그대로 출력합니다.
+    // F11을 누르면 커널 모니터로 들어가고, F12를 누르면 인터럽트 통계를 보여줍니다.
+    monitor::set_enabled(true);
+    loop {
+        match input::wait() {
+            InputEvent::Key(KeyEvent { code: KeyCode::F11, state: KeyState::Down, .. }) => {
+                x86_64::instructions::interrupts::int3();
+            }
+            InputEvent::Key(KeyEvent { code: KeyCode::F12, state: KeyState::Down, .. }) => {
+                println!("\n{}", interrupt_stats::report());
+            }
+            InputEvent::Key(KeyEvent { ch: Some(ch), .. }) => print!("{}", ch),
+            _ => {}
+        }
+    }
 }
 
 // 패닉이 발생했을 때, 이 함수가 호출됩니다.
@@ -34,7 +63,7 @@ pub extern "C" fn _start() -> ! {
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!("{}", info);
-    loop {}
+    blog_os::hlt_loop();
 }
 
 #[cfg(test)]
@@ -0,0 +1,223 @@
+//! 가상 메모리 관리
+//!
+//! 부트로더는 map_physical_memory 기능으로 물리 메모리 전체를 가상 주소 `physical_memory_offset`부터 매핑해 줍니다.
+//! 그래서 페이지 테이블이 있는 물리 프레임도 `offset + 물리 주소`로 바로 읽고 쓸 수 있고,
+//! x86_64 크레이트의 OffsetPageTable이 이 방법으로 페이지 테이블을 다룹니다.
+//!
+//! 페이지 테이블을 바꾼 뒤에는 TLB에 남아 있는 예전 변환을 지워야 합니다. 매핑을 지우거나 권한을 바꿀 때
+//! 지우지 않으면 CPU가 한동안 예전 매핑으로 접근합니다. 여기 있는 함수들은 바꾼 페이지를 모두 TLB에서 지웁니다.
+use bootloader::BootInfo;
+use core::ptr;
+use spin::{Mutex, Once};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::{FlagUpdateError, MapToError, TranslateResult, UnmapError};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PhysFrame,
+    Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::frame_allocator::{self, BitmapFrameAllocator};
+use crate::stack;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryError {
+    /// `init`이 아직 호출되지 않았습니다.
+    NotInitialized,
+    /// 페이지 테이블이나 새 페이지에 쓸 빈 프레임이 없습니다.
+    OutOfFrames,
+    /// 이미 다른 프레임에 매핑된 페이지
+    AlreadyMapped(PhysFrame),
+    NotMapped,
+    /// 상위 페이지 테이블 항목이 2 MiB 또는 1 GiB 페이지라서 4 KiB 페이지로 다룰 수 없습니다.
+    HugePage,
+    /// 페이지 테이블 항목이 올바르지 않은 물리 주소를 가리킵니다.
+    InvalidFrame(PhysAddr),
+}
+
+impl From<MapToError<Size4KiB>> for MemoryError {
+    fn from(error: MapToError<Size4KiB>) -> MemoryError {
+        match error {
+            MapToError::FrameAllocationFailed => MemoryError::OutOfFrames,
+            MapToError::ParentEntryHugePage => MemoryError::HugePage,
+            MapToError::PageAlreadyMapped(frame) => MemoryError::AlreadyMapped(frame),
+        }
+    }
+}
+
+impl From<UnmapError> for MemoryError {
+    fn from(error: UnmapError) -> MemoryError {
+        match error {
+            UnmapError::ParentEntryHugePage => MemoryError::HugePage,
+            UnmapError::PageNotMapped => MemoryError::NotMapped,
+            UnmapError::InvalidFrameAddress(address) => MemoryError::InvalidFrame(address),
+        }
+    }
+}
+
+impl From<FlagUpdateError> for MemoryError {
+    fn from(error: FlagUpdateError) -> MemoryError {
+        match error {
+            FlagUpdateError::PageNotMapped => MemoryError::NotMapped,
+            FlagUpdateError::ParentEntryHugePage => MemoryError::HugePage,
+        }
+    }
+}
+
+static PHYSICAL_MEMORY_OFFSET: Once<VirtAddr> = Once::new();
+static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+
+/// 물리 프레임 할당기와 페이지 테이블을 초기화하고, 커널 스택의 guard page 매핑을 지웁니다.
+///
+/// # Safety
+/// 부트로더가 넘겨준 `boot_info`여야 하고, 한 번만 호출해야 합니다.
+pub unsafe fn init(boot_info: &'static BootInfo) {
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    frame_allocator::init(&boot_info.memory_map);
+    PHYSICAL_MEMORY_OFFSET.call_once(|| physical_memory_offset);
+
+    let mut mapper = OffsetPageTable::new(active_level_4_table(physical_memory_offset), physical_memory_offset);
+    stack::unmap_guard_pages(&mut mapper).expect("failed to unmap stack guard pages");
+    *MAPPER.lock() = Some(mapper);
+}
+
+// CR3가 가리키는 레벨 4 페이지 테이블. 같은 테이블에 대한 &mut가 둘 이상 생기지 않도록 init에서만 부릅니다.
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    let (frame, _) = Cr3::read();
+    &mut *(physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr()
+}
+
+/// 물리 메모리 전체가 매핑된 가상 주소. `init` 전에는 None입니다.
+pub fn physical_memory_offset() -> Option<VirtAddr> {
+    PHYSICAL_MEMORY_OFFSET.r#try().copied()
+}
+
+/// 물리 주소를 읽고 쓸 수 있는 가상 주소로 바꿉니다.
+pub fn phys_to_virt(address: PhysAddr) -> Option<VirtAddr> {
+    physical_memory_offset().map(|offset| offset + address.as_u64())
+}
+
+fn with_mapper<T>(f: impl FnOnce(&mut OffsetPageTable<'static>) -> Result<T, MemoryError>) -> Result<T, MemoryError> {
+    // 인터럽트 핸들러가 페이지 테이블을 바꾸는 도중에 끼어들어 같은 잠금을 기다리지 않도록 인터럽트를 끕니다.
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut mapper = MAPPER.lock();
+        f(mapper.as_mut().ok_or(MemoryError::NotInitialized)?)
+    })
+}
+
+/// 새 프레임을 할당해 0으로 채우고 `page`에 매핑합니다. PRESENT 플래그는 자동으로 붙습니다.
+pub fn map(page: Page, flags: PageTableFlags) -> Result<PhysFrame, MemoryError> {
+    let mut allocator = BitmapFrameAllocator;
+    let frame = allocator.allocate_frame().ok_or(MemoryError::OutOfFrames)?;
+    // 새 프레임에는 예전에 쓰던 값이 남아 있으므로, 유저 프로그램 등에 보이기 전에 지웁니다.
+    let virt = phys_to_virt(frame.start_address()).ok_or(MemoryError::NotInitialized)?;
+    unsafe { ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, Size4KiB::SIZE as usize) };
+
+    match unsafe { map_to(page, frame, flags) } {
+        Ok(()) => Ok(frame),
+        Err(error) => {
+            unsafe { allocator.deallocate_frame(frame) };
+            Err(error)
+        }
+    }
+}
+
+/// `page`를 이미 정해진 `frame`에 매핑합니다. MMIO 레지스터처럼 물리 주소가 정해진 곳에 씁니다.
+/// 중간 단계의 페이지 테이블이 없으면 프레임 할당기에서 새로 받습니다.
+///
+/// # Safety
+/// 같은 프레임을 여러 곳에 매핑하면 서로 다른 &mut가 같은 메모리를 가리킬 수 있습니다. 호출하는 쪽이 이를 막아야 합니다.
+pub unsafe fn map_to(page: Page, frame: PhysFrame, flags: PageTableFlags) -> Result<(), MemoryError> {
+    with_mapper(|mapper| {
+        let flush = mapper.map_to(page, frame, flags | PageTableFlags::PRESENT, &mut BitmapFrameAllocator)?;
+        flush.flush();
+        Ok(())
+    })
+}
+
+/// `page`의 매핑을 지우고 매핑되어 있던 프레임을 돌려줍니다. 프레임을 해제하는 것은 호출하는 쪽의 일입니다.
+/// 비게 된 중간 단계의 페이지 테이블은 해제하지 않습니다.
+pub fn unmap(page: Page) -> Result<PhysFrame, MemoryError> {
+    with_mapper(|mapper| {
+        let (frame, flush) = mapper.unmap(page)?;
+        flush.flush();
+        Ok(frame)
+    })
+}
+
+/// `page`의 플래그를 `flags`로 바꿉니다. (읽기 전용으로 만들기, 실행 금지 등) PRESENT 플래그는 자동으로 붙습니다.
+///
+/// # Safety
+/// 지금 쓰고 있는 메모리를 읽기 전용으로 바꾸거나 커널 코드를 실행 금지로 바꾸면 바로 page fault가 발생합니다.
+pub unsafe fn protect(page: Page, flags: PageTableFlags) -> Result<(), MemoryError> {
+    with_mapper(|mapper| {
+        let flush = mapper.update_flags(page, flags | PageTableFlags::PRESENT)?;
+        flush.flush();
+        Ok(())
+    })
+}
+
+/// 가상 주소가 매핑된 물리 주소와 그 페이지의 플래그. 큰 페이지(2 MiB, 1 GiB)도 처리합니다.
+pub fn translate(address: VirtAddr) -> Result<(PhysAddr, PageTableFlags), MemoryError> {
+    with_mapper(|mapper| match mapper.translate(address) {
+        TranslateResult::Mapped { frame, offset, flags } => Ok((frame.start_address() + offset, flags)),
+        TranslateResult::NotMapped => Err(MemoryError::NotMapped),
+        TranslateResult::InvalidFrameAddress(address) => Err(MemoryError::InvalidFrame(address)),
+    })
+}
+
+/*
+    디버깅용 주소 변환
+
+    페이지 테이블을 CR3부터 직접 따라가므로 MAPPER의 잠금을 잡지 않습니다. 잠금을 쥔 채로 멈춘 코드를
+    커널 모니터에서 살펴볼 때도 쓸 수 있습니다. 큰 페이지를 만나면 그 페이지 안의 주소를 계산합니다.
+*/
+/// 지금 페이지 테이블에서 `address`가 가리키는 물리 주소. 매핑되어 있지 않거나 `init` 전이면 None입니다.
+pub fn translate_addr(address: VirtAddr) -> Option<PhysAddr> {
+    let offset = physical_memory_offset()?;
+    let (level_4_frame, _) = Cr3::read();
+    let indexes = [address.p4_index(), address.p3_index(), address.p2_index(), address.p1_index()];
+
+    let mut frame_address = level_4_frame.start_address();
+    for (level, &index) in indexes.iter().enumerate() {
+        let table = unsafe { &*(offset + frame_address.as_u64()).as_ptr::<PageTable>() };
+        let entry = &table[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        frame_address = entry.addr();
+        // 레벨 3(1 GiB)과 레벨 2(2 MiB) 항목은 HUGE_PAGE 플래그가 있으면 그 자체가 페이지입니다.
+        if level > 0 && level < 3 && entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            let page_size = 1u64 << (30 - 9 * (level as u64 - 1));
+            return Some(frame_address + (address.as_u64() & (page_size - 1)));
+        }
+    }
+    Some(frame_address + u64::from(address.page_offset()))
+}
+
+#[test_case]
+fn test_map_protect_unmap() {
+    // 아무것도 쓰지 않는 하위 절반의 주소 (allocator::HEAP_START와 겹치지 않게 합니다.)
+    let page = Page::containing_address(VirtAddr::new(0x3333_3333_0000));
+    let free = frame_allocator::stats().free;
+
+    let frame = map(page, PageTableFlags::WRITABLE).unwrap();
+    assert_eq!(map(page, PageTableFlags::WRITABLE), Err(MemoryError::AlreadyMapped(frame)));
+    let address = page.start_address() + 0x123u64;
+    assert_eq!(translate_addr(address), Some(frame.start_address() + 0x123u64));
+    assert_eq!(translate(address).map(|(phys, _)| phys), Ok(frame.start_address() + 0x123u64));
+
+    unsafe {
+        *address.as_mut_ptr::<u8>() = 42;
+        protect(page, PageTableFlags::empty()).unwrap();
+    }
+    let (_, flags) = translate(address).unwrap();
+    assert!(!flags.contains(PageTableFlags::WRITABLE));
+    assert_eq!(unsafe { *address.as_ptr::<u8>() }, 42);
+
+    assert_eq!(unmap(page), Ok(frame));
+    assert_eq!(translate_addr(address), None);
+    assert_eq!(unmap(page), Err(MemoryError::NotMapped));
+    unsafe { BitmapFrameAllocator.deallocate_frame(frame) };
+    // 중간 단계의 페이지 테이블로 쓴 프레임은 돌아오지 않습니다.
+    assert!(frame_allocator::stats().free <= free);
+}
@@ -0,0 +1,400 @@
+//! 커널 모니터
+//!
+//! int3(breakpoint)을 만나면 커널을 멈추고 시리얼 포트나 VGA 화면과 키보드로 명령을 받는 간단한 디버거입니다.
+//! 레지스터와 메모리를 보고, 포트를 읽거나 쓰고, 트랩 플래그로 명령어 하나씩 실행할 수 있습니다.
+//! 기본적으로는 꺼져 있으며, 꺼져 있을 때의 int3은 예전처럼 스택 프레임만 출력하고 돌아갑니다.
+use core::convert::TryFrom;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrameValue};
+use x86_64::VirtAddr;
+use crate::keyboard::{Decoder, KeyState};
+use crate::{allocator, interrupt_stats, keyboard, memory, power, ps2, serial, stack, vga_buffer, watchdog};
+
+const DEBUG_VECTOR: u8 = 1;
+const BREAKPOINT_VECTOR: u8 = 3;
+const RFLAGS_TRAP_FLAG: u64 = 1 << 8;
+
+/// 예외가 발생한 시점의 범용 레지스터. 진입 코드가 push한 순서의 역순입니다.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Registers {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+}
+
+/// 진입 코드가 저장한 레지스터와 CPU가 저장한 스택 프레임. 값을 바꾸면 돌아갈 때 그대로 반영됩니다.
+#[repr(C)]
+pub struct TrapFrame {
+    pub registers: Registers,
+    pub stack_frame: InterruptStackFrameValue,
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let r = &self.registers;
+        let frame = &self.stack_frame;
+        writeln!(f, "rax={:016x} rbx={:016x} rcx={:016x}", r.rax, r.rbx, r.rcx)?;
+        writeln!(f, "rdx={:016x} rsi={:016x} rdi={:016x}", r.rdx, r.rsi, r.rdi)?;
+        writeln!(f, "rbp={:016x} rsp={:016x} r8 ={:016x}", r.rbp, frame.stack_pointer.as_u64(), r.r8)?;
+        writeln!(f, "r9 ={:016x} r10={:016x} r11={:016x}", r.r9, r.r10, r.r11)?;
+        writeln!(f, "r12={:016x} r13={:016x} r14={:016x}", r.r12, r.r13, r.r14)?;
+        writeln!(f, "r15={:016x} rip={:016x} rfl={:016x}", r.r15, frame.instruction_pointer.as_u64(), frame.cpu_flags)?;
+        writeln!(f, "cs={:04x} ss={:04x}", frame.code_segment, frame.stack_segment)?;
+        writeln!(
+            f,
+            "cr0={:016x} cr2={:016x} cr3={:016x} cr4={:016x}",
+            Cr0::read_raw(),
+            Cr2::read().as_u64(),
+            Cr3::read().0.start_address().as_u64(),
+            Cr4::read_raw(),
+        )
+    }
+}
+
+/*
+    진입 코드
+
+    x86-interrupt 함수는 범용 레지스터를 보여주지도, 바꾸게 해주지도 않습니다.
+    그래서 직접 모든 범용 레지스터를 스택에 저장하고, 그 위치를 TrapFrame 포인터로 Rust 함수에 넘깁니다.
+    CPU는 스택 프레임(40바이트)을 쌓기 전에 rsp를 16바이트로 정렬하므로, 레지스터 15개(120바이트)를 push하면
+    call 직전의 rsp가 다시 16바이트로 정렬되어 System V 호출 규약을 만족합니다.
+    커널은 SSE를 사용하지 않도록 빌드하므로 부동소수점 레지스터는 저장하지 않습니다.
+
+    워치독의 NMI 진입 코드도 같은 매크로로 만들기 때문에 호출할 Rust 함수를 인자로 받습니다.
+*/
+macro_rules! trap_entry {
+    ($name:literal, $vector:literal, $handler:literal) => {
+        core::arch::global_asm!(concat!(
+            ".global ", $name, "\n",
+            $name, ":\n",
+            "    push rax\n    push rbx\n    push rcx\n    push rdx\n    push rsi\n",
+            "    push rdi\n    push rbp\n    push r8\n    push r9\n    push r10\n",
+            "    push r11\n    push r12\n    push r13\n    push r14\n    push r15\n",
+            "    mov rdi, rsp\n",
+            "    mov esi, ", $vector, "\n",
+            "    cld\n",
+            "    call ", $handler, "\n",
+            "    pop r15\n    pop r14\n    pop r13\n    pop r12\n    pop r11\n",
+            "    pop r10\n    pop r9\n    pop r8\n    pop rbp\n    pop rdi\n",
+            "    pop rsi\n    pop rdx\n    pop rcx\n    pop rbx\n    pop rax\n",
+            "    iretq\n",
+        ));
+    };
+}
+
+pub(crate) use trap_entry;
+
+trap_entry!("monitor_debug_entry", 1, "monitor_trap");
+trap_entry!("monitor_breakpoint_entry", 3, "monitor_trap");
+
+extern "C" {
+    fn monitor_debug_entry();
+    fn monitor_breakpoint_entry();
+}
+
+/// breakpoint와 debug 예외에 모니터의 진입 코드를 등록합니다.
+pub fn register(idt: &mut InterruptDescriptorTable) {
+    unsafe {
+        idt.debug.set_handler_addr(VirtAddr::new(monitor_debug_entry as usize as u64));
+        idt.breakpoint.set_handler_addr(VirtAddr::new(monitor_breakpoint_entry as usize as u64));
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// step 명령으로 트랩 플래그를 켠 뒤에 발생한 debug 예외인지 구분합니다.
+static STEPPING: AtomicBool = AtomicBool::new(false);
+
+/// int3을 만났을 때 모니터로 들어갈지 정합니다.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+#[no_mangle]
+extern "C" fn monitor_trap(frame: &mut TrapFrame, vector: u64) {
+    let entry = interrupt_stats::enter();
+    let vector = vector as u8;
+
+    let enter_monitor = if vector == DEBUG_VECTOR && STEPPING.swap(false, Ordering::Relaxed) {
+        frame.stack_frame.cpu_flags &= !RFLAGS_TRAP_FLAG;
+        true
+    } else {
+        let name = if vector == BREAKPOINT_VECTOR { "BREAKPOINT" } else { "DEBUG" };
+        crate::println!("EXCEPTION: {}\n{:#?}", name, frame.stack_frame);
+        vector == BREAKPOINT_VECTOR && ENABLED.load(Ordering::Relaxed)
+    };
+    interrupt_stats::record(vector, entry);
+
+    if enter_monitor {
+        run(frame);
+    }
+}
+
+// 모니터의 출력은 VGA 화면과 시리얼 포트에 모두 보냅니다.
+macro_rules! out {
+    ($($arg:tt)*) => {{
+        vga_buffer::_print(format_args!($($arg)*));
+        serial::_print(format_args!($($arg)*));
+    }};
+}
+
+const LINE_LENGTH: usize = 78;
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F;
+
+/*
+    입력
+
+    모니터는 인터럽트가 꺼진 예외 핸들러 안에서 실행되므로 키보드 인터럽트와 입력 큐를 사용할 수 없습니다.
+    대신 시리얼 포트의 라인 상태와 PS/2 컨트롤러의 상태 레지스터를 직접 확인(polling)해서 한 바이트씩 읽습니다.
+*/
+struct Console {
+    keyboard: Decoder,
+}
+
+impl Console {
+    fn new() -> Console {
+        Console {
+            keyboard: Decoder::new(keyboard::scancode_set()),
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = serial::try_read_byte() {
+                return if byte == b'\r' { b'\n' } else { byte };
+            }
+
+            let status = ps2::status();
+            if status & ps2::STATUS_OUTPUT_FULL != 0 {
+                let byte = ps2::read_data().unwrap_or(0);
+                // 마우스가 보낸 바이트는 버립니다.
+                if status & ps2::STATUS_AUX_DATA == 0 {
+                    if let Some(event) = self.keyboard.add_byte(byte) {
+                        if let (KeyState::Down, Some(ch)) = (event.state, event.ch) {
+                            if ch.is_ascii() {
+                                return ch as u8;
+                            }
+                        }
+                    }
+                }
+            }
+            // 모니터에 머무는 동안에는 타이머 틱이 멈추므로, 워치독이 커널이 멈췄다고 판단하지 않게 합니다.
+            watchdog::touch();
+            core::hint::spin_loop();
+        }
+    }
+
+    fn read_line<'a>(&mut self, buffer: &'a mut [u8; LINE_LENGTH]) -> &'a str {
+        let mut len = 0;
+        loop {
+            match self.read_byte() {
+                b'\n' => break,
+                BACKSPACE | DELETE if len > 0 => {
+                    len -= 1;
+                    // VGA 화면은 지울 수 없으므로 시리얼 쪽만 커서를 되돌립니다.
+                    serial::_print(format_args!("\x08 \x08"));
+                }
+                byte @ 0x20..=0x7E if len < LINE_LENGTH => {
+                    buffer[len] = byte;
+                    len += 1;
+                    out!("{}", byte as char);
+                }
+                _ => {}
+            }
+        }
+        out!("\n");
+        core::str::from_utf8(&buffer[..len]).unwrap_or("")
+    }
+}
+
+enum Action {
+    Stay,
+    Continue,
+    Step,
+}
+
+fn run(frame: &mut TrapFrame) {
+    out!("Entering monitor. Type 'help' for a list of commands.\n");
+    let mut console = Console::new();
+    let mut buffer = [0; LINE_LENGTH];
+    loop {
+        out!("monitor> ");
+        let line = console.read_line(&mut buffer);
+        match execute(line, frame) {
+            Ok(Action::Stay) => {}
+            Ok(Action::Continue) => return,
+            Ok(Action::Step) => {
+                STEPPING.store(true, Ordering::Relaxed);
+                frame.stack_frame.cpu_flags |= RFLAGS_TRAP_FLAG;
+                return;
+            }
+            Err(message) => out!("error: {}\n", message),
+        }
+    }
+}
+
+const HELP: &str = "\
+help                    show this message
+regs                    dump registers
+x <addr> [len]          hex dump memory (the address must be mapped)
+v2p <addr>              translate a virtual address to a physical address
+in <port> [b|w|d]       read from an I/O port
+out <port> <val> [b|w|d] write to an I/O port
+irq                     show interrupt statistics
+stacks                  show peak usage of each kernel stack
+heap                    show kernel heap statistics
+s, step                 execute one instruction
+c, continue             leave the monitor
+reboot                  reset the machine
+";
+
+const DEFAULT_DUMP_LENGTH: u64 = 64;
+const MAX_DUMP_LENGTH: u64 = 4096;
+
+fn execute(line: &str, frame: &mut TrapFrame) -> Result<Action, &'static str> {
+    let mut args = line.split_whitespace();
+    let command = match args.next() {
+        Some(command) => command,
+        None => return Ok(Action::Stay),
+    };
+
+    match command {
+        "help" | "h" | "?" => out!("{}", HELP),
+        "regs" | "r" => out!("{}", frame),
+        "x" => {
+            let address = next_number(&mut args, "usage: x <addr> [len]")?;
+            let len = match args.next() {
+                Some(text) => parse_number(text).ok_or("invalid number")?,
+                None => DEFAULT_DUMP_LENGTH,
+            };
+            hex_dump(address, len.min(MAX_DUMP_LENGTH))?;
+        }
+        "v2p" => {
+            let address = next_number(&mut args, "usage: v2p <addr>")?;
+            let address = VirtAddr::try_new(address).map_err(|_| "not a canonical address")?;
+            match memory::translate_addr(address) {
+                Some(phys) => out!("{:#x} -> {:#x}\n", address.as_u64(), phys.as_u64()),
+                None => out!("{:#x} is not mapped\n", address.as_u64()),
+            }
+        }
+        "in" => {
+            let port = port_number(next_number(&mut args, "usage: in <port> [b|w|d]")?)?;
+            let value = match args.next().unwrap_or("b") {
+                "b" => u32::from(unsafe { Port::<u8>::new(port).read() }),
+                "w" => u32::from(unsafe { Port::<u16>::new(port).read() }),
+                "d" => unsafe { Port::<u32>::new(port).read() },
+                _ => return Err("width must be b, w or d"),
+            };
+            out!("{:#06x}: {:#x}\n", port, value);
+        }
+        "out" => {
+            let port = port_number(next_number(&mut args, "usage: out <port> <val> [b|w|d]")?)?;
+            let value = next_number(&mut args, "usage: out <port> <val> [b|w|d]")?;
+            unsafe {
+                match args.next().unwrap_or("b") {
+                    "b" => Port::<u8>::new(port).write(value as u8),
+                    "w" => Port::<u16>::new(port).write(value as u16),
+                    "d" => Port::<u32>::new(port).write(value as u32),
+                    _ => return Err("width must be b, w or d"),
+                }
+            }
+        }
+        "irq" => out!("{}", interrupt_stats::report()),
+        "stacks" => out!("{}", stack::report()),
+        "heap" => out!("{} allocator\n{}", allocator::allocator_name(), allocator::stats()),
+        "s" | "step" => return Ok(Action::Step),
+        "c" | "continue" => return Ok(Action::Continue),
+        "reboot" => power::reboot(),
+        _ => return Err("unknown command, try 'help'"),
+    }
+    Ok(Action::Stay)
+}
+
+fn next_number<'a>(args: &mut impl Iterator<Item = &'a str>, usage: &'static str) -> Result<u64, &'static str> {
+    parse_number(args.next().ok_or(usage)?).ok_or("invalid number")
+}
+
+/// `0x`로 시작하면 16진수, 아니면 10진수로 읽습니다.
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn port_number(value: u64) -> Result<u16, &'static str> {
+    u16::try_from(value).map_err(|_| "port must be below 0x10000")
+}
+
+const PAGE_SIZE: u64 = 4096;
+
+// 한 줄에 16바이트씩, 16진수와 출력 가능한 ASCII 문자를 함께 보여줍니다.
+// 읽기 전에 페이지마다 매핑을 확인하고, 매핑되지 않은 페이지는 읽지 않고 건너뜁니다.
+fn hex_dump(address: u64, len: u64) -> Result<(), &'static str> {
+    let end = address.checked_add(len).ok_or("address range overflows")?;
+    let mut line = address;
+    while line < end {
+        let page_end = (line & !(PAGE_SIZE - 1)).saturating_add(PAGE_SIZE);
+        if !is_mapped(line) {
+            out!("{:016x}: not mapped\n", line);
+            line = page_end.min(end);
+            continue;
+        }
+        // 줄이 페이지 경계를 넘으면 다음 페이지는 다음 줄에서 확인합니다.
+        let count = (end - line).min(16).min(page_end - line) as usize;
+        let bytes = unsafe { core::slice::from_raw_parts(line as *const u8, count) };
+        out!("{:016x}:", line);
+        for byte in bytes {
+            out!(" {:02x}", byte);
+        }
+        for _ in count..16 {
+            out!("   ");
+        }
+        out!("  ");
+        for &byte in bytes {
+            out!("{}", if (0x20..0x7F).contains(&byte) { byte as char } else { '.' });
+        }
+        out!("\n");
+        line += count as u64;
+    }
+    Ok(())
+}
+
+fn is_mapped(address: u64) -> bool {
+    VirtAddr::try_new(address).ok().and_then(memory::translate_addr).is_some()
+}
+
+#[test_case]
+fn test_parse_number() {
+    assert_eq!(parse_number("0x3f8"), Some(0x3F8));
+    assert_eq!(parse_number("0XFF"), Some(0xFF));
+    assert_eq!(parse_number("1024"), Some(1024));
+    assert_eq!(parse_number("0xg"), None);
+    assert_eq!(parse_number(""), None);
+    assert_eq!(port_number(0x10000), Err("port must be below 0x10000"));
+}
+
+#[test_case]
+fn test_hex_dump_rejects_bad_ranges() {
+    assert_eq!(hex_dump(0xFFFF_FFFF_FFFF_FFF0, 64), Err("address range overflows"));
+    // 매핑되지 않은 주소와 canonical이 아닌 주소는 읽지 않습니다.
+    assert!(!is_mapped(0xdeadbeaf000));
+    assert!(!is_mapped(0x8000_0000_0000));
+    assert!(is_mapped(HELP.as_ptr() as u64));
+}
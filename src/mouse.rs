@@ -0,0 +1,256 @@
+//! PS/2 마우스 드라이버
+//!
+//! 마우스는 PS/2 컨트롤러의 두 번째 포트에 연결되어 IRQ12를 발생시킵니다.
+//! 인터럽트마다 한 바이트씩 들어오며, 3바이트(휠이 있는 마우스는 4바이트)가 모여야 하나의 패킷이 됩니다.
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use crate::input::{self, InputEvent};
+use crate::interrupts::{self, IrqError};
+use crate::ps2::{self, Ps2Error};
+use crate::workqueue;
+
+const DATA_PORT: u16 = 0x60;
+
+// 마우스 명령
+const SET_DEFAULTS: u8 = 0xF6;
+const ENABLE_REPORTING: u8 = 0xF4;
+const SET_SAMPLE_RATE: u8 = 0xF3;
+const GET_DEVICE_ID: u8 = 0xF2;
+
+// 장치 ID. 특정 sample rate 시퀀스를 보내면 확장 기능이 켜지고 ID가 바뀝니다.
+const ID_STANDARD: u8 = 0;
+const ID_INTELLIMOUSE: u8 = 3;
+const ID_INTELLIMOUSE_EXPLORER: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MouseButtons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+    pub fourth: bool,
+    pub fifth: bool,
+}
+
+/// 패킷 하나에 해당하는 이동량과 버튼 상태.
+/// `dy`는 화면 좌표계를 따라 아래쪽이 양수입니다.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub dx: i16,
+    pub dy: i16,
+    pub wheel: i8,
+    pub buttons: MouseButtons,
+}
+
+/*
+    패킷의 첫 번째 바이트
+
+    bit 0: 왼쪽 버튼      bit 4: X 부호
+    bit 1: 오른쪽 버튼    bit 5: Y 부호
+    bit 2: 가운데 버튼    bit 6: X 오버플로
+    bit 3: 항상 1        bit 7: Y 오버플로
+
+    두 번째, 세 번째 바이트는 X, Y 이동량의 하위 8비트입니다.
+    네 번째 바이트는 ID 3이면 8비트 휠 이동량, ID 4이면 4비트 휠 이동량과 네 번째, 다섯 번째 버튼입니다.
+*/
+const LEFT_BUTTON: u8 = 1 << 0;
+const RIGHT_BUTTON: u8 = 1 << 1;
+const MIDDLE_BUTTON: u8 = 1 << 2;
+const ALWAYS_ONE: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+/// 바이트를 모아 패킷을 만듭니다. 장치 ID에 따라 패킷의 길이와 네 번째 바이트의 형식이 다릅니다.
+pub struct PacketDecoder {
+    packet: [u8; 4],
+    index: usize,
+    device_id: u8,
+    packet_size: usize,
+}
+
+impl PacketDecoder {
+    pub const fn new(device_id: u8) -> PacketDecoder {
+        let packet_size = match device_id {
+            ID_INTELLIMOUSE | ID_INTELLIMOUSE_EXPLORER => 4,
+            _ => 3,
+        };
+        PacketDecoder {
+            packet: [0; 4],
+            index: 0,
+            device_id,
+            packet_size,
+        }
+    }
+
+    pub fn add_byte(&mut self, byte: u8) -> Option<MouseEvent> {
+        // 첫 바이트의 3번 비트가 꺼져 있다면 패킷 경계를 놓친 것이므로 버리고 다시 맞춥니다.
+        if self.index == 0 && byte & ALWAYS_ONE == 0 {
+            return None;
+        }
+        self.packet[self.index] = byte;
+        self.index += 1;
+        if self.index < self.packet_size {
+            return None;
+        }
+        self.index = 0;
+        Some(self.decode())
+    }
+
+    fn decode(&self) -> MouseEvent {
+        let [flags, x, y, extra] = self.packet;
+
+        let movement = |value: u8, sign: u8, overflow: u8| -> i16 {
+            if flags & overflow != 0 {
+                0
+            } else if flags & sign != 0 {
+                i16::from(value) - 0x100
+            } else {
+                i16::from(value)
+            }
+        };
+        let (wheel, fourth, fifth) = match self.device_id {
+            // 네 번째 바이트 전체가 휠 이동량입니다.
+            ID_INTELLIMOUSE => (extra as i8, false, false),
+            // 하위 4비트는 2의 보수로 표현된 -8 ~ 7 사이의 휠 값이고, 4번과 5번 비트가 네 번째, 다섯 번째 버튼입니다.
+            ID_INTELLIMOUSE_EXPLORER => (((extra << 4) as i8) >> 4, extra & (1 << 4) != 0, extra & (1 << 5) != 0),
+            _ => (0, false, false),
+        };
+
+        MouseEvent {
+            dx: movement(x, X_SIGN, X_OVERFLOW),
+            dy: -movement(y, Y_SIGN, Y_OVERFLOW),
+            wheel,
+            buttons: MouseButtons {
+                left: flags & LEFT_BUTTON != 0,
+                right: flags & RIGHT_BUTTON != 0,
+                middle: flags & MIDDLE_BUTTON != 0,
+                fourth,
+                fifth,
+            },
+        }
+    }
+}
+
+static DECODER: Mutex<PacketDecoder> = Mutex::new(PacketDecoder::new(ID_STANDARD));
+
+const IRQ: u8 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseError {
+    Controller(Ps2Error),
+    Irq(IrqError),
+}
+
+impl From<Ps2Error> for MouseError {
+    fn from(err: Ps2Error) -> Self {
+        MouseError::Controller(err)
+    }
+}
+
+impl From<IrqError> for MouseError {
+    fn from(err: IrqError) -> Self {
+        MouseError::Irq(err)
+    }
+}
+
+/*
+    초기화 순서
+
+    1. 컨트롤러의 두 번째 포트를 켭니다.
+    2. 마우스를 기본 설정으로 되돌립니다.
+    3. sample rate를 200, 100, 80 순서로 설정하면 휠을 지원하는 마우스는 ID 3으로 바뀝니다.
+       ID 3이 되었다면 200, 200, 80을 한 번 더 보냅니다. 버튼이 5개인 마우스는 그때 ID 4로 바뀝니다.
+    4. 데이터 전송(streaming)을 켭니다.
+    5. 설정 바이트에서 두 번째 포트의 클럭과 IRQ12를 켜고 핸들러를 등록합니다.
+*/
+pub fn init() -> Result<(), MouseError> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        ps2::enable_aux_port()?;
+        ps2::flush_output();
+
+        ps2::send_to_aux(SET_DEFAULTS)?;
+        let mut device_id = match knock(&[200, 100, 80])? {
+            ID_INTELLIMOUSE => ID_INTELLIMOUSE,
+            _ => ID_STANDARD,
+        };
+        if device_id == ID_INTELLIMOUSE && knock(&[200, 200, 80])? == ID_INTELLIMOUSE_EXPLORER {
+            device_id = ID_INTELLIMOUSE_EXPLORER;
+        }
+        *DECODER.lock() = PacketDecoder::new(device_id);
+
+        ps2::send_to_aux(ENABLE_REPORTING)?;
+
+        let config = ps2::read_config()?;
+        ps2::write_config(
+            (config | ps2::CONFIG_SECOND_PORT_IRQ) & !ps2::CONFIG_SECOND_PORT_CLOCK_DISABLED,
+        )?;
+        ps2::flush_output();
+
+        interrupts::register_irq(IRQ, handle_interrupt)?;
+        Ok(())
+    })
+}
+
+// sample rate를 차례로 설정한 뒤 바뀐 장치 ID를 읽습니다.
+fn knock(rates: &[u8]) -> Result<u8, Ps2Error> {
+    for &rate in rates {
+        ps2::send_to_aux(SET_SAMPLE_RATE)?;
+        ps2::send_to_aux(rate)?;
+    }
+    ps2::send_to_aux(GET_DEVICE_ID)?;
+    ps2::read_data()
+}
+
+// IRQ12는 보조 PIC에서 오지만, EOI는 IRQ 진입점이 두 PIC 모두에 보내줍니다.
+fn handle_interrupt() {
+    // 초기화 중에 직접 읽어간 바이트 때문에 데이터 없이 인터럽트가 올 수 있습니다.
+    let status = ps2::status();
+    if status & ps2::STATUS_OUTPUT_FULL == 0 || status & ps2::STATUS_AUX_DATA == 0 {
+        return;
+    }
+    let byte: u8 = unsafe { Port::new(DATA_PORT).read() };
+
+    let _ = workqueue::schedule(decode_byte, usize::from(byte));
+}
+
+// 패킷 조립은 작업 큐에서 합니다.
+fn decode_byte(byte: usize) {
+    if let Some(event) = DECODER.lock().add_byte(byte as u8) {
+        input::push(InputEvent::Mouse(event));
+    }
+}
+
+#[test_case]
+fn test_decode_negative_movement() {
+    let mut decoder = PacketDecoder::new(ID_STANDARD);
+    assert_eq!(decoder.add_byte(ALWAYS_ONE | X_SIGN | LEFT_BUTTON), None);
+    assert_eq!(decoder.add_byte(0xFE), None);
+    let event = decoder.add_byte(0x05).unwrap();
+    assert_eq!((event.dx, event.dy), (-2, -5));
+    assert!(event.buttons.left && !event.buttons.right);
+}
+
+#[test_case]
+fn test_decode_wheel_and_resync() {
+    let mut decoder = PacketDecoder::new(ID_INTELLIMOUSE_EXPLORER);
+    // 3번 비트가 꺼진 바이트는 패킷의 시작이 될 수 없습니다.
+    assert_eq!(decoder.add_byte(0x00), None);
+    decoder.add_byte(ALWAYS_ONE);
+    decoder.add_byte(0);
+    decoder.add_byte(0);
+    let event = decoder.add_byte(0x0F).unwrap();
+    assert_eq!(event.wheel, -1);
+}
+
+#[test_case]
+fn test_intellimouse_wheel_is_a_full_byte() {
+    let mut decoder = PacketDecoder::new(ID_INTELLIMOUSE);
+    decoder.add_byte(ALWAYS_ONE);
+    decoder.add_byte(0);
+    decoder.add_byte(0);
+    // ID 3에서 0xFF는 휠을 아래로 한 칸 굴린 것이고 버튼은 눌리지 않았습니다.
+    let event = decoder.add_byte(0xFF).unwrap();
+    assert_eq!(event.wheel, -1);
+    assert!(!event.buttons.fourth && !event.buttons.fifth);
+}
@@ -0,0 +1,205 @@
+//! 8259 PIC (Programmable Interrupt Controller)
+//!
+//! 주 PIC와 보조 PIC가 연결(cascade)되어 15개의 IRQ 라인을 처리합니다. 보조 PIC는 주 PIC의 IRQ2에 연결되어 있습니다.
+//! 각 PIC에는 세 개의 8비트 레지스터가 있습니다.
+//! - IMR (Interrupt Mask Register): 1인 라인은 CPU에 전달되지 않습니다.
+//! - IRR (Interrupt Request Register): 장치가 요청했지만 아직 CPU에 전달되지 않은 라인
+//! - ISR (In-Service Register): CPU에 전달되었고 EOI를 기다리는 라인
+//! 이 모듈의 함수들은 두 PIC의 레지스터를 합쳐 IRQ n을 n번 비트로 하는 16비트 값으로 다룹니다.
+use core::sync::atomic::{AtomicU64, Ordering};
+use pic8259::ChainedPics;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+/*
+    PIC의 기본 구성은 CPU에 0–15 범위의 Interrupt vector number를 보내기 때문에 사용할 수 없습니다.
+    현재 저 범위의 number는 이미 CPU exception에 의해 점유되고 있습니다.
+    예를 들어 숫자 8은 double fault에 해당합니다.
+    이 겹치는 문제를 해결하려면 PIC 인터럽트를 다른 번호로 다시 매핑해야 합니다.
+    실제 범위는 예외와 겹치지 않는 한 중요하지 않지만 일반적으로 32-47 범위가 선택됩니다.
+    이는 32개의 예외 슬롯 이후 첫 번째 여유 번호이기 때문입니다.
+*/
+
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+pub const CASCADE_IRQ: u8 = 2;
+
+const PIC_1_COMMAND_PORT: u16 = 0x20;
+const PIC_1_DATA_PORT: u16 = 0x21;
+const PIC_2_COMMAND_PORT: u16 = 0xA0;
+const PIC_2_DATA_PORT: u16 = 0xA1;
+
+// OCW3: 다음 명령 포트 읽기가 IRR(0x0A) 또는 ISR(0x0B)을 돌려주도록 선택합니다.
+const READ_IRR: u8 = 0x0A;
+const READ_ISR: u8 = 0x0B;
+const EOI: u8 = 0x20;
+
+/*
+    위에서 언급한 바와 같이 사진의 오프셋을 32-47 범위로 설정합니다.
+    Chained Pics 구조를 Mutex로 감싸면 다음 단계에서 필요한 (잠금 방법을 통해) 안전한 가변 액세스를 얻을 수 있습니다.
+    그러나 잘못된 오프셋으로 인해 정의되지 않은 동작이 발생할 수 있으므로 ChainedPics::new 함수는 안전하지 않습니다.
+*/
+
+/*
+    IMR에는 두 가지 마스크를 합쳐서 씁니다.
+    - 라인 마스크: 드라이버가 mask/unmask로 여닫는 라인. 핸들러가 등록된 라인만 열려 있습니다.
+    - 우선순위 마스크: 중첩 인터럽트를 처리하는 동안 잠시 막아 둔, 우선순위가 같거나 낮은 라인
+    두 마스크를 따로 두어야 중첩 처리가 끝나고 우선순위 마스크를 되돌릴 때 드라이버의 설정을 덮어쓰지 않습니다.
+*/
+struct Pics {
+    chained: ChainedPics,
+    line_mask: u16,
+    priority_mask: u16,
+}
+
+impl Pics {
+    fn write_imr(&self) {
+        let mask = self.line_mask | self.priority_mask;
+        unsafe {
+            Port::<u8>::new(PIC_1_DATA_PORT).write(mask as u8);
+            Port::<u8>::new(PIC_2_DATA_PORT).write((mask >> 8) as u8);
+        }
+    }
+}
+
+static PICS: Mutex<Pics> = Mutex::new(Pics {
+    chained: unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) },
+    line_mask: 0xFFFF,
+    priority_mask: 0,
+});
+
+// 인터럽트 핸들러도 PICS를 잠그므로(EOI) 핸들러 밖에서 잠글 때는 인터럽트를 꺼야 합니다. 아니면 잠금을 쥔 채 중단되어 deadlock이 생깁니다.
+fn with_pics<R>(f: impl FnOnce(&mut Pics) -> R) -> R {
+    without_interrupts(|| f(&mut PICS.lock()))
+}
+
+/// PIC를 초기화하고 모든 라인을 마스크합니다. 핸들러가 등록된 라인만 register_irq가 마스크를 해제합니다.
+pub fn init() {
+    with_pics(|pics| {
+        unsafe { pics.chained.initialize() };
+        pics.line_mask = 0xFFFF;
+        pics.priority_mask = 0;
+        pics.write_imr();
+    });
+}
+
+pub fn mask(line: u8) {
+    with_pics(|pics| {
+        pics.line_mask |= 1 << line;
+        pics.write_imr();
+    });
+}
+
+/// 보조 PIC의 IRQ(8~15)가 전달되려면 보조 PIC가 연결된 주 PIC의 IRQ2도 열려 있어야 하므로 함께 엽니다.
+pub fn unmask(line: u8) {
+    with_pics(|pics| {
+        pics.line_mask &= !(1 << line);
+        if line >= 8 {
+            pics.line_mask &= !(1 << CASCADE_IRQ);
+        }
+        pics.write_imr();
+    });
+}
+
+pub fn is_masked(line: u8) -> bool {
+    imr() & (1 << line) != 0
+}
+
+/// 모든 라인을 마스크합니다. APIC로 전환할 때 사용합니다.
+pub fn disable() {
+    with_pics(|pics| {
+        pics.line_mask = 0xFFFF;
+        pics.write_imr();
+    });
+}
+
+/// 실제 IMR 값 (라인 마스크와 우선순위 마스크를 합친 값)
+pub fn imr() -> u16 {
+    with_pics(|_| unsafe {
+        u16::from(Port::<u8>::new(PIC_1_DATA_PORT).read())
+            | u16::from(Port::<u8>::new(PIC_2_DATA_PORT).read()) << 8
+    })
+}
+
+fn read_command_register(ocw3: u8) -> u16 {
+    with_pics(|_| unsafe {
+        let mut pic_1: Port<u8> = Port::new(PIC_1_COMMAND_PORT);
+        let mut pic_2: Port<u8> = Port::new(PIC_2_COMMAND_PORT);
+        pic_1.write(ocw3);
+        pic_2.write(ocw3);
+        u16::from(pic_1.read()) | u16::from(pic_2.read()) << 8
+    })
+}
+
+pub fn irr() -> u16 {
+    read_command_register(READ_IRR)
+}
+
+pub fn isr() -> u16 {
+    read_command_register(READ_ISR)
+}
+
+pub fn end_of_interrupt(line: u8) {
+    with_pics(|pics| unsafe {
+        pics.chained.notify_end_of_interrupt(PIC_1_OFFSET + line);
+    });
+}
+
+/// 우선순위 마스크를 바꾸고 이전 값을 돌려줍니다.
+pub fn set_priority_mask(mask: u16) -> u16 {
+    with_pics(|pics| {
+        let previous = pics.priority_mask;
+        pics.priority_mask = mask;
+        pics.write_imr();
+        previous
+    })
+}
+
+pub fn priority_mask() -> u16 {
+    with_pics(|pics| pics.priority_mask)
+}
+
+/*
+    Spurious IRQ
+
+    PIC가 CPU에 인터럽트를 알린 뒤 CPU가 벡터를 받아가기 전에 IRQ 신호가 사라지면(잡음, 너무 짧은 펄스 등),
+    PIC는 우선순위가 가장 낮은 라인(주 PIC는 IRQ7, 보조 PIC는 IRQ15)의 벡터를 대신 보냅니다.
+    이 경우 해당 라인은 ISR(In-Service Register)에 표시되지 않으므로 ISR을 읽어 진짜 인터럽트인지 확인합니다.
+
+    - 주 PIC의 spurious IRQ7: 아무 PIC에도 EOI를 보내지 않습니다.
+    - 보조 PIC의 spurious IRQ15: 보조 PIC에는 보내지 않지만, 주 PIC는 IRQ2(cascade)를 진짜 인터럽트로 받았으므로 주 PIC에만 EOI를 보냅니다.
+
+    잘못된 EOI는 다른 라인의 ISR 비트를 지워 버려, 처리 중인 인터럽트보다 낮은 우선순위의 인터럽트가 끼어들게 만듭니다.
+*/
+static SPURIOUS_IRQS: AtomicU64 = AtomicU64::new(0);
+
+/// `line`으로 들어온 인터럽트가 spurious IRQ인지 확인하고, 그렇다면 필요한 EOI를 보냅니다.
+pub fn check_spurious(line: u8) -> bool {
+    if (line != 7 && line != 15) || isr() & (1 << line) != 0 {
+        return false;
+    }
+
+    SPURIOUS_IRQS.fetch_add(1, Ordering::Relaxed);
+    if line == 15 {
+        with_pics(|_| unsafe { Port::<u8>::new(PIC_1_COMMAND_PORT).write(EOI) });
+    }
+    true
+}
+
+/// 감지된 spurious IRQ7/IRQ15의 수
+pub fn spurious_irq_count() -> u64 {
+    SPURIOUS_IRQS.load(Ordering::Relaxed)
+}
+
+#[test_case]
+fn test_mask_and_unmask() {
+    // IRQ5는 QEMU 기본 구성에서 사용되지 않는 라인입니다.
+    unmask(5);
+    assert!(!is_masked(5));
+    mask(5);
+    assert!(is_masked(5));
+    // 핸들러 밖에서는 처리 중인 인터럽트가 없습니다.
+    assert_eq!(isr(), 0);
+}
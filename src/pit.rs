@@ -0,0 +1,98 @@
+//! 8253/8254 PIT (Programmable Interval Timer)
+//!
+//! PIT는 1.193182 MHz 클럭을 16비트 분주값(divisor)으로 나눠 주기적인 신호를 만듭니다.
+//! 채널 0의 출력은 IRQ0에 연결되어 있고, 채널 2의 출력은 PC 스피커에 연결되어 있습니다.
+//! 전원이 켜진 직후에는 분주값이 65536이라서 약 18.2 Hz로 IRQ0이 발생합니다.
+//! 채널 2는 인터럽트 없이 0x61 포트로 게이트와 출력을 확인할 수 있어서 다른 시계를 보정할 때 사용합니다.
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL_0_PORT: u16 = 0x40;
+const CHANNEL_2_PORT: u16 = 0x42;
+const COMMAND_PORT: u16 = 0x43;
+const SPEAKER_PORT: u16 = 0x61;
+
+/*
+    명령 바이트
+
+    bit 6-7: 채널 (00 = 채널 0, 10 = 채널 2)
+    bit 4-5: 접근 방식 (11 = 하위 바이트 다음 상위 바이트)
+    bit 1-3: 동작 모드 (000 = mode 0, 카운트가 끝나면 출력이 1이 됨 / 010 = mode 2, rate generator)
+    bit 0:   BCD 모드 (0 = 16비트 이진수)
+*/
+const CHANNEL_0: u8 = 0b00 << 6;
+const CHANNEL_2: u8 = 0b10 << 6;
+const ACCESS_LOW_HIGH: u8 = 0b11 << 4;
+const MODE_TERMINAL_COUNT: u8 = 0b000 << 1;
+const MODE_RATE_GENERATOR: u8 = 0b010 << 1;
+
+// 0x61 포트: bit 0은 채널 2의 게이트, bit 1은 스피커 출력, bit 5는 채널 2의 출력 상태입니다.
+const SPEAKER_GATE: u8 = 1 << 0;
+const SPEAKER_DATA: u8 = 1 << 1;
+const CHANNEL_2_OUTPUT: u8 = 1 << 5;
+
+// 분주값 0은 65536을 뜻합니다.
+const MAX_DIVISOR: u32 = 0x10000;
+
+// 명령 포트는 모든 채널이 함께 사용하므로, 명령과 분주값을 쓰는 동안 다른 코드가 끼어들지 못하게 합니다.
+static PIT: Mutex<()> = Mutex::new(());
+
+/// 원하는 주파수에 가장 가까운 분주값 (1 ~ 65536)
+pub fn divisor_for(frequency: u32) -> u32 {
+    if frequency == 0 {
+        return MAX_DIVISOR;
+    }
+    ((BASE_FREQUENCY + frequency / 2) / frequency).clamp(1, MAX_DIVISOR)
+}
+
+/// 채널 0이 `BASE_FREQUENCY / divisor` Hz로 IRQ0을 발생시키도록 설정합니다.
+pub fn set_channel_0_divisor(divisor: u32) {
+    // 65536은 u16으로 바꾸면 0이 되므로 그대로 써도 됩니다.
+    let divisor = divisor.clamp(1, MAX_DIVISOR) as u16;
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let _pit = PIT.lock();
+        unsafe {
+            Port::<u8>::new(COMMAND_PORT).write(CHANNEL_0 | ACCESS_LOW_HIGH | MODE_RATE_GENERATOR);
+            let mut data: Port<u8> = Port::new(CHANNEL_0_PORT);
+            data.write(divisor as u8);
+            data.write((divisor >> 8) as u8);
+        }
+    });
+}
+
+/// 채널 2로 PIT 클럭이 `count`번 지날 때까지 바쁜 대기(busy wait)합니다.
+/// 인터럽트를 사용하지 않으므로 인터럽트가 꺼져 있어도 동작합니다.
+pub fn busy_wait(count: u16) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let _pit = PIT.lock();
+        unsafe {
+            let mut speaker: Port<u8> = Port::new(SPEAKER_PORT);
+            // 스피커는 끄고, 카운트를 설정하는 동안에는 게이트를 닫아 둡니다.
+            let control = speaker.read() & !(SPEAKER_DATA | SPEAKER_GATE);
+            speaker.write(control);
+
+            Port::<u8>::new(COMMAND_PORT).write(CHANNEL_2 | ACCESS_LOW_HIGH | MODE_TERMINAL_COUNT);
+            let mut data: Port<u8> = Port::new(CHANNEL_2_PORT);
+            data.write(count as u8);
+            data.write((count >> 8) as u8);
+
+            // 게이트를 열면 카운트가 시작되고, 0에 도달하면 출력이 1이 됩니다.
+            speaker.write(control | SPEAKER_GATE);
+            while speaker.read() & CHANNEL_2_OUTPUT == 0 {
+                core::hint::spin_loop();
+            }
+            speaker.write(control);
+        }
+    });
+}
+
+#[test_case]
+fn test_divisor_for() {
+    assert_eq!(divisor_for(1000), 1193);
+    assert_eq!(divisor_for(100), 11932);
+    // 너무 낮거나 높은 주파수는 분주값의 범위로 잘립니다.
+    assert_eq!(divisor_for(1), MAX_DIVISOR);
+    assert_eq!(divisor_for(BASE_FREQUENCY * 2), 1);
+}
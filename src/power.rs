@@ -0,0 +1,132 @@
+//! 재부팅과 전원 끄기
+use crate::acpi;
+use x86_64::instructions::{hlt, interrupts, port::Port};
+
+/*
+    재부팅
+
+    PS/2 컨트롤러(8042)의 출력 포트에는 CPU의 리셋 라인이 연결되어 있어서, 명령 포트(0x64)에 0xFE를 쓰면 CPU가 리셋됩니다.
+    컨트롤러가 없거나 명령을 무시하는 경우에는 비어 있는 IDT를 로드한 뒤 예외를 발생시켜 triple fault를 일으킵니다.
+    예외 핸들러를 찾지 못한 CPU는 double fault 핸들러도 찾지 못하고, 결국 스스로 리셋됩니다.
+*/
+const KBC_STATUS_PORT: u16 = 0x64;
+const KBC_COMMAND_PORT: u16 = 0x64;
+const KBC_INPUT_BUFFER_FULL: u8 = 1 << 1;
+const KBC_PULSE_RESET_LINE: u8 = 0xFE;
+
+/// 컴퓨터를 재부팅합니다.
+pub fn reboot() -> ! {
+    interrupts::disable();
+    unsafe {
+        pulse_reset_line();
+        triple_fault()
+    }
+}
+
+unsafe fn pulse_reset_line() {
+    let mut status: Port<u8> = Port::new(KBC_STATUS_PORT);
+    let mut command: Port<u8> = Port::new(KBC_COMMAND_PORT);
+
+    // 컨트롤러가 이전 명령을 다 읽어갈 때까지 기다립니다.
+    for _ in 0..0x10000 {
+        if status.read() & KBC_INPUT_BUFFER_FULL == 0 {
+            break;
+        }
+    }
+    command.write(KBC_PULSE_RESET_LINE);
+
+    // 리셋이 실제로 일어날 때까지 약간의 시간이 걸릴 수 있습니다.
+    for _ in 0..0x100000 {
+        core::hint::spin_loop();
+    }
+}
+
+unsafe fn triple_fault() -> ! {
+    use x86_64::instructions::tables::lidt;
+    use x86_64::structures::DescriptorTablePointer;
+    use x86_64::VirtAddr;
+
+    let empty_idt = DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::zero(),
+    };
+    lidt(&empty_idt);
+    x86_64::instructions::interrupts::int3();
+    halt()
+}
+
+/*
+    전원 끄기
+
+    ACPI 규격의 S5(soft off) 상태로 진입하려면 DSDT의 \_S5 객체에서 얻은 SLP_TYP 값과 SLP_EN 비트를
+    FADT에 적힌 PM1a(그리고 있다면 PM1b) 제어 레지스터에 씁니다.
+    ACPI 테이블은 init_memory가 acpi::init을 부른 뒤부터 읽을 수 있습니다. 그 전이거나 테이블을 읽을 수 없는 경우에는
+    에뮬레이터들이 제공하는 종료 포트를 차례대로 시도합니다.
+*/
+const SLP_EN: u16 = 1 << 13;
+const SCI_EN: u16 = 1;
+
+// (포트, 값) 쌍: QEMU(최신 버전), Bochs와 이전 버전의 QEMU, VirtualBox
+const EMULATOR_SHUTDOWN_PORTS: [(u16, u16); 3] = [(0x604, 0x2000), (0xB004, 0x2000), (0x4004, 0x3400)];
+
+/// 컴퓨터의 전원을 끕니다. 모든 방법이 실패하면 CPU를 멈춘 상태로 남겨둡니다.
+pub fn shutdown() -> ! {
+    interrupts::disable();
+    unsafe {
+        if let Some(fadt) = acpi::fadt() {
+            acpi_shutdown(&fadt);
+        }
+        emulator_shutdown();
+    }
+    halt()
+}
+
+unsafe fn acpi_shutdown(fadt: &acpi::Fadt) {
+    let (slp_typa, slp_typb) = match acpi::s5_sleep_types(fadt) {
+        Some(types) => types,
+        None => return,
+    };
+    if fadt.pm1a_control_block == 0 {
+        return;
+    }
+
+    enable_acpi_mode(fadt);
+
+    let mut pm1a_control: Port<u16> = Port::new(fadt.pm1a_control_block as u16);
+    pm1a_control.write((slp_typa << 10) | SLP_EN);
+    if fadt.pm1b_control_block != 0 {
+        let mut pm1b_control: Port<u16> = Port::new(fadt.pm1b_control_block as u16);
+        pm1b_control.write((slp_typb << 10) | SLP_EN);
+    }
+}
+
+// 펌웨어가 아직 레거시 모드라면 SMI 명령 포트로 ACPI 모드 전환을 요청합니다.
+unsafe fn enable_acpi_mode(fadt: &acpi::Fadt) {
+    let mut pm1a_control: Port<u16> = Port::new(fadt.pm1a_control_block as u16);
+    if pm1a_control.read() & SCI_EN != 0 || fadt.smi_command_port == 0 || fadt.acpi_enable == 0 {
+        return;
+    }
+
+    let mut smi_command: Port<u8> = Port::new(fadt.smi_command_port as u16);
+    smi_command.write(fadt.acpi_enable);
+    for _ in 0..0x100000 {
+        if pm1a_control.read() & SCI_EN != 0 {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+unsafe fn emulator_shutdown() {
+    for &(port, value) in EMULATOR_SHUTDOWN_PORTS.iter() {
+        Port::new(port).write(value);
+    }
+}
+
+// 인터럽트를 끈 채로 hlt하면 NMI 외에는 CPU를 깨울 수 없습니다.
+fn halt() -> ! {
+    loop {
+        interrupts::disable();
+        hlt();
+    }
+}
@@ -0,0 +1,114 @@
+//! PS/2 컨트롤러(8042)
+//!
+//! 컨트롤러에는 두 개의 장치 포트가 있습니다. 첫 번째 포트에는 보통 키보드가(IRQ1), 두 번째 포트(aux)에는 마우스가(IRQ12) 연결됩니다.
+//! 두 장치 모두 데이터 포트(0x60)를 함께 사용하고, 명령 포트(0x64)로는 컨트롤러 자체에 명령을 내립니다.
+use x86_64::instructions::port::Port;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+// 상태 레지스터
+pub const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+pub const STATUS_INPUT_FULL: u8 = 1 << 1;
+pub const STATUS_AUX_DATA: u8 = 1 << 5;
+
+// 컨트롤러 명령
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const ENABLE_AUX_PORT: u8 = 0xA8;
+const WRITE_TO_AUX: u8 = 0xD4;
+
+// 설정 바이트
+pub const CONFIG_FIRST_PORT_IRQ: u8 = 1 << 0;
+pub const CONFIG_SECOND_PORT_IRQ: u8 = 1 << 1;
+pub const CONFIG_SECOND_PORT_CLOCK_DISABLED: u8 = 1 << 5;
+pub const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+// 장치의 응답
+const ACK: u8 = 0xFA;
+const RESEND: u8 = 0xFE;
+
+const TIMEOUT: usize = 100_000;
+const RETRIES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// 컨트롤러가 정해진 시간 안에 응답하지 않았습니다.
+    Timeout,
+    /// 장치가 ACK 대신 다른 값을 보냈습니다.
+    UnexpectedResponse(u8),
+}
+
+pub fn status() -> u8 {
+    unsafe { Port::new(STATUS_PORT).read() }
+}
+
+fn wait_for_input_empty() -> Result<(), Ps2Error> {
+    (0..TIMEOUT)
+        .find(|_| status() & STATUS_INPUT_FULL == 0)
+        .map(|_| ())
+        .ok_or(Ps2Error::Timeout)
+}
+
+fn wait_for_output_full() -> Result<(), Ps2Error> {
+    (0..TIMEOUT)
+        .find(|_| status() & STATUS_OUTPUT_FULL != 0)
+        .map(|_| ())
+        .ok_or(Ps2Error::Timeout)
+}
+
+pub fn write_command(command: u8) -> Result<(), Ps2Error> {
+    wait_for_input_empty()?;
+    unsafe { Port::new(COMMAND_PORT).write(command) };
+    Ok(())
+}
+
+pub fn write_data(data: u8) -> Result<(), Ps2Error> {
+    wait_for_input_empty()?;
+    unsafe { Port::new(DATA_PORT).write(data) };
+    Ok(())
+}
+
+pub fn read_data() -> Result<u8, Ps2Error> {
+    wait_for_output_full()?;
+    Ok(unsafe { Port::new(DATA_PORT).read() })
+}
+
+/// 출력 버퍼에 남아 있는 바이트를 모두 버립니다.
+pub fn flush_output() {
+    for _ in 0..16 {
+        if status() & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+        let _: u8 = unsafe { Port::new(DATA_PORT).read() };
+    }
+}
+
+pub fn read_config() -> Result<u8, Ps2Error> {
+    write_command(READ_CONFIG)?;
+    read_data()
+}
+
+pub fn write_config(config: u8) -> Result<(), Ps2Error> {
+    write_command(WRITE_CONFIG)?;
+    write_data(config)
+}
+
+pub fn enable_aux_port() -> Result<(), Ps2Error> {
+    write_command(ENABLE_AUX_PORT)
+}
+
+/// 두 번째 포트의 장치에 명령 바이트를 보내고 ACK를 기다립니다. Resend를 받으면 다시 보냅니다.
+pub fn send_to_aux(byte: u8) -> Result<(), Ps2Error> {
+    for _ in 0..RETRIES {
+        write_command(WRITE_TO_AUX)?;
+        write_data(byte)?;
+        match read_data()? {
+            ACK => return Ok(()),
+            RESEND => continue,
+            other => return Err(Ps2Error::UnexpectedResponse(other)),
+        }
+    }
+    Err(Ps2Error::UnexpectedResponse(RESEND))
+}
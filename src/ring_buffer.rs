@@ -0,0 +1,78 @@
+//! 고정 크기 링 버퍼
+//!
+//! 인터럽트 핸들러와 작업 큐 사이에서 데이터를 주고받는 큐는 핸들러 안에서도 넣을 수 있어야 하는데,
+//! 핸들러에서는 할당하지 않으므로(allocator 모듈 참고) 크기가 컴파일 시간에 정해진 배열 위에 만듭니다.
+
+pub struct RingBuffer<T: Copy, const N: usize> {
+    items: [Option<T>; N],
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    pub const fn new() -> Self {
+        RingBuffer {
+            items: [None; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// 큐가 가득 차 있으면 넣으려던 값을 `Err`로 돌려줍니다.
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(item);
+        }
+        self.items[(self.head + self.len) % N] = Some(item);
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let item = self.items[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        item
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<T: Copy, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test_case]
+fn test_ring_buffer_fifo_order() {
+    let mut buffer: RingBuffer<u8, 4> = RingBuffer::new();
+    for i in 0..4 {
+        assert_eq!(buffer.push(i), Ok(()));
+    }
+    assert_eq!(buffer.push(4), Err(4));
+    assert_eq!(buffer.pop(), Some(0));
+    assert_eq!(buffer.push(4), Ok(()));
+    for i in 1..5 {
+        assert_eq!(buffer.pop(), Some(i));
+    }
+    assert_eq!(buffer.pop(), None);
+    assert!(buffer.is_empty());
+}
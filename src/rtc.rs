@@ -0,0 +1,281 @@
+//! CMOS RTC (Real-Time Clock)
+//!
+//! RTC는 전원이 꺼져 있어도 배터리로 날짜와 시간을 유지합니다. CMOS 메모리의 레지스터로 읽으며,
+//! 0x70 포트에 레지스터 번호를 쓰고 0x71 포트로 값을 읽거나 씁니다.
+//! 값의 형식(BCD 또는 이진수, 12시간 또는 24시간)은 펌웨어 설정에 따라 다르므로 상태 레지스터 B를 보고 해석합니다.
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use crate::acpi;
+use crate::interrupts::{self, IrqError};
+
+const INDEX_PORT: u16 = 0x70;
+const DATA_PORT: u16 = 0x71;
+
+mod reg {
+    pub const SECONDS: u8 = 0x00;
+    pub const MINUTES: u8 = 0x02;
+    pub const HOURS: u8 = 0x04;
+    pub const DAY: u8 = 0x07;
+    pub const MONTH: u8 = 0x08;
+    pub const YEAR: u8 = 0x09;
+    pub const STATUS_A: u8 = 0x0A;
+    pub const STATUS_B: u8 = 0x0B;
+    pub const STATUS_C: u8 = 0x0C;
+}
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_A_RATE_MASK: u8 = 0x0F;
+const STATUS_B_PERIODIC_INTERRUPT: u8 = 1 << 6;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+// 12시간 형식에서 시 레지스터의 최상위 비트는 오후(PM)를 뜻합니다.
+const HOUR_PM: u8 = 1 << 7;
+
+// 0x70 포트에 쓴 레지스터 번호는 다음 0x71 접근까지 유지되어야 하므로, 두 포트 접근을 한 번에 잠급니다.
+static CMOS: Mutex<()> = Mutex::new(());
+
+unsafe fn read_register(register: u8) -> u8 {
+    Port::<u8>::new(INDEX_PORT).write(register);
+    Port::<u8>::new(DATA_PORT).read()
+}
+
+unsafe fn write_register(register: u8, value: u8) {
+    Port::<u8>::new(INDEX_PORT).write(register);
+    Port::<u8>::new(DATA_PORT).write(value);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// 1970-01-01 00:00:00 UTC로부터 지난 초. RTC는 UTC로 맞춰져 있다고 가정합니다.
+    pub fn unix_timestamp(&self) -> i64 {
+        let days = days_from_civil(i64::from(self.year), i64::from(self.month), i64::from(self.day));
+        days * 86400 + i64::from(self.hour) * 3600 + i64::from(self.minute) * 60 + i64::from(self.second)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+// 그레고리력 날짜를 1970-01-01로부터의 일 수로 바꿉니다. (Howard Hinnant의 days_from_civil)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12; // 3월이 0
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// RTC 레지스터 값 그대로
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: Option<u8>,
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+fn decode(raw: RawTime, status_b: u8) -> DateTime {
+    let binary = |value: u8| if status_b & STATUS_B_BINARY != 0 { value } else { bcd_to_binary(value) };
+
+    // PM 비트는 BCD 변환 전에 떼어내야 합니다.
+    let pm = status_b & STATUS_B_24_HOUR == 0 && raw.hour & HOUR_PM != 0;
+    let mut hour = binary(raw.hour & !HOUR_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12시간 형식은 12, 1, ..., 11 순서이므로 12시는 0시(오전) 또는 12시(오후)가 됩니다.
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    // 세기 레지스터가 없으면 2000년대라고 가정합니다.
+    let century = raw.century.map(binary).unwrap_or(20);
+    DateTime {
+        year: u16::from(century) * 100 + u16::from(binary(raw.year)),
+        month: binary(raw.month),
+        day: binary(raw.day),
+        hour,
+        minute: binary(raw.minute),
+        second: binary(raw.second),
+    }
+}
+
+fn century_register() -> Option<u8> {
+    acpi::fadt()
+        .map(|fadt| fadt.century_register)
+        .filter(|&register| register != 0)
+}
+
+unsafe fn read_raw(century_register: Option<u8>) -> RawTime {
+    // 갱신 중(UIP)에 읽으면 일부 레지스터만 바뀐 값을 읽을 수 있습니다.
+    while read_register(reg::STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    RawTime {
+        second: read_register(reg::SECONDS),
+        minute: read_register(reg::MINUTES),
+        hour: read_register(reg::HOURS),
+        day: read_register(reg::DAY),
+        month: read_register(reg::MONTH),
+        year: read_register(reg::YEAR),
+        century: century_register.map(|register| read_register(register)),
+    }
+}
+
+/// RTC에서 현재 날짜와 시간을 읽습니다.
+pub fn now() -> DateTime {
+    let century_register = century_register();
+    without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        unsafe {
+            // UIP 확인과 읽기 사이에 갱신이 시작될 수 있으므로, 같은 값을 두 번 연속 읽을 때까지 반복합니다.
+            let mut last = read_raw(century_register);
+            loop {
+                let current = read_raw(century_register);
+                if current == last {
+                    break decode(current, read_register(reg::STATUS_B));
+                }
+                last = current;
+            }
+        }
+    })
+}
+
+/*
+    주기 인터럽트 (IRQ8)
+
+    RTC는 32768 Hz 클럭을 2^(rate - 1)로 나눈 주기로 IRQ8을 발생시킬 수 있습니다. (rate 3 = 8192 Hz, rate 15 = 2 Hz)
+    인터럽트를 받은 뒤에는 상태 레지스터 C를 읽어야 다음 인터럽트가 발생합니다.
+*/
+const IRQ: u8 = 8;
+const MIN_RATE: u8 = 3;
+const MAX_RATE: u8 = 15;
+
+static PERIODIC_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// 주기 인터럽트의 주파수 (Hz)
+pub fn periodic_frequency(rate: u8) -> u32 {
+    32768 >> (rate.clamp(MIN_RATE, MAX_RATE) - 1)
+}
+
+/// `rate`(3 ~ 15)에 해당하는 주기로 RTC 인터럽트를 켭니다.
+pub fn enable_periodic_interrupt(rate: u8) -> Result<(), IrqError> {
+    let rate = rate.clamp(MIN_RATE, MAX_RATE);
+    interrupts::register_irq(IRQ, handle_interrupt)?;
+    without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        unsafe {
+            let status_a = read_register(reg::STATUS_A);
+            write_register(reg::STATUS_A, (status_a & !STATUS_A_RATE_MASK) | rate);
+            let status_b = read_register(reg::STATUS_B);
+            write_register(reg::STATUS_B, status_b | STATUS_B_PERIODIC_INTERRUPT);
+            // 이미 대기 중인 인터럽트 플래그를 지웁니다.
+            read_register(reg::STATUS_C);
+        }
+    });
+    Ok(())
+}
+
+/// RTC 인터럽트를 끄고 IRQ8 핸들러를 해제합니다.
+pub fn disable_periodic_interrupt() -> Result<(), IrqError> {
+    without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        unsafe {
+            let status_b = read_register(reg::STATUS_B);
+            write_register(reg::STATUS_B, status_b & !STATUS_B_PERIODIC_INTERRUPT);
+        }
+    });
+    interrupts::unregister_irq(IRQ).map(|_| ())
+}
+
+fn handle_interrupt() {
+    let _cmos = CMOS.lock();
+    unsafe { read_register(reg::STATUS_C) };
+    PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 주기 인터럽트가 발생한 횟수
+pub fn periodic_ticks() -> u64 {
+    PERIODIC_TICKS.load(Ordering::Relaxed)
+}
+
+#[test_case]
+fn test_decode_bcd_12_hour() {
+    let raw = RawTime {
+        second: 0x59,
+        minute: 0x30,
+        hour: HOUR_PM | 0x12,
+        day: 0x31,
+        month: 0x12,
+        year: 0x99,
+        century: Some(0x19),
+    };
+    let time = decode(raw, 0);
+    assert_eq!(time, DateTime { year: 1999, month: 12, day: 31, hour: 12, minute: 30, second: 59 });
+
+    // 오전 12시는 0시입니다.
+    let time = decode(RawTime { hour: 0x12, ..raw }, 0);
+    assert_eq!(time.hour, 0);
+}
+
+#[test_case]
+fn test_decode_binary_24_hour() {
+    let raw = RawTime { second: 5, minute: 4, hour: 23, day: 2, month: 1, year: 24, century: None };
+    let time = decode(raw, STATUS_B_BINARY | STATUS_B_24_HOUR);
+    assert_eq!(time, DateTime { year: 2024, month: 1, day: 2, hour: 23, minute: 4, second: 5 });
+}
+
+#[test_case]
+fn test_unix_timestamp() {
+    let epoch = DateTime { year: 1970, month: 1, day: 1, hour: 0, minute: 0, second: 0 };
+    assert_eq!(epoch.unix_timestamp(), 0);
+    let leap_day = DateTime { year: 2024, month: 2, day: 29, hour: 12, minute: 0, second: 0 };
+    assert_eq!(leap_day.unix_timestamp(), 1_709_208_000);
+}
+
+#[test_case]
+fn test_periodic_interrupt() {
+    use core::time::Duration;
+    fn handler() {}
+
+    // rate 6 = 1024 Hz
+    let before = periodic_ticks();
+    assert_eq!(enable_periodic_interrupt(6), Ok(()));
+    assert_eq!(enable_periodic_interrupt(6), Err(IrqError::AlreadyRegistered(IRQ)));
+    crate::time::sleep(Duration::from_millis(20));
+    assert!(periodic_ticks() > before);
+
+    // 끈 뒤에는 IRQ8을 다른 핸들러가 쓸 수 있습니다.
+    assert_eq!(disable_periodic_interrupt(), Ok(()));
+    assert_eq!(interrupts::register_irq(IRQ, handler), Ok(()));
+    assert!(interrupts::unregister_irq(IRQ).is_ok());
+    assert_eq!(disable_periodic_interrupt(), Err(IrqError::NotRegistered(IRQ)));
+}
@@ -3,21 +3,48 @@ use uart_16550::SerialPort;
 use spin::Mutex;
 use lazy_static::lazy_static;
 
+const COM1: u16 = 0x3F8;
+// 수신 데이터는 COM1 + 0, 라인 상태 레지스터는 COM1 + 5에 있습니다.
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+
 //static를 사용하여 메서드가 처음 사용할 때 lazy_static가 정확히 한 번만 호출되도록 할 수 있습니다.
 lazy_static! {
     pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+        let mut serial_port = unsafe { SerialPort::new(COM1) };
         serial_port.init();
         Mutex::new(serial_port)
     };
 }
 
+/// 수신된 바이트가 있으면 읽고, 없으면 기다리지 않고 `None`을 돌려줍니다.
+/// SERIAL1을 잠그지 않으므로 인터럽트가 꺼진 예외 핸들러 안에서도 사용할 수 있습니다.
+pub fn try_read_byte() -> Option<u8> {
+    use x86_64::instructions::port::Port;
+
+    unsafe {
+        let line_status: u8 = Port::new(COM1 + 5).read();
+        if line_status & LINE_STATUS_DATA_READY == 0 {
+            return None;
+        }
+        Some(Port::new(COM1).read())
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
     SERIAL1.lock().write_fmt(args).expect("Printing to serial failed");
 }
 
+/// SERIAL1을 잠그지 않고 COM1에 직접 씁니다.
+/// 잠금을 쥔 코드가 멈춘 상태(워치독, NMI)에서만 사용해야 하며, 다른 출력과 글자가 섞일 수 있습니다.
+pub fn emergency_print(args: ::core::fmt::Arguments) {
+    use core::fmt::Write;
+    // COM1은 SERIAL1이 이미 초기화했으므로 다시 초기화하지 않습니다.
+    let mut port = unsafe { SerialPort::new(COM1) };
+    let _ = port.write_fmt(args);
+}
+
 /// Prints to the host through the serial interface.
 #[macro_export]
 macro_rules! serial_print {
@@ -0,0 +1,265 @@
+//! 커널 스택 할당
+//!
+//! 스택은 아래로 자라므로 넘치면 바로 아래에 있는 메모리를 덮어씁니다. 스택을 그냥 static 배열로 만들면
+//! 넘친 스택이 .bss의 다른 변수를 조용히 망가뜨립니다. 여기서는 모든 스택 아래에 guard page를 하나씩 두고,
+//! 페이지 테이블을 다룰 수 있게 되면 guard page의 매핑을 지워서 넘치는 순간 page fault가 나게 합니다.
+//! 매핑은 `init_memory`의 `memory::init`에서 지우므로, 그 전(`init`을 실행하는 동안)에 넘친 스택은 막지 못합니다.
+//!
+//! 스택은 페이지 단위로 정렬된 static 영역에서 앞에서부터 잘라 씁니다. 부팅 중에 한 번만 만들고 해제하지 않습니다.
+//! 부트로더가 만든 커널 스택도 함께 등록해서 page fault가 어느 스택의 guard page에서 났는지 찾을 수 있게 합니다.
+//!
+//! 스택의 크기를 정할 수 있도록, 만들 때 스택을 정해진 패턴으로 채워 두고 나중에 패턴이 지워진 가장 깊은 곳을 찾아
+//! 지금까지 가장 많이 쓴 양(high-water mark)을 계산합니다.
+use core::arch::asm;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::structures::paging::{Mapper, Page, Size4KiB};
+use x86_64::structures::paging::mapper::UnmapError;
+use x86_64::VirtAddr;
+
+pub const PAGE_SIZE: usize = 4096;
+const AREA_PAGES: usize = 32;
+const MAX_STACKS: usize = 8;
+
+#[repr(C, align(4096))]
+struct StackArea([u8; AREA_PAGES * PAGE_SIZE]);
+
+static mut AREA: StackArea = StackArea([0; AREA_PAGES * PAGE_SIZE]);
+// 다음에 잘라 줄 페이지 번호
+static NEXT_PAGE: AtomicUsize = AtomicUsize::new(0);
+static GUARDS_UNMAPPED: AtomicBool = AtomicBool::new(false);
+
+// 아직 쓰지 않은 스택을 채우는 값. 스택에 흔히 올라오는 0이나 작은 정수, 커널 주소와 겹치지 않는 값을 고릅니다.
+const FILL_PATTERN: u64 = 0x57AC_4B1D_57AC_4B1D;
+
+/// guard page와 그 위의 스택 영역
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stack {
+    name: &'static str,
+    guard: Page<Size4KiB>,
+    bottom: VirtAddr,
+    top: VirtAddr,
+}
+
+impl Stack {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// 스택의 가장 낮은 주소. 이 아래는 guard page입니다.
+    pub fn bottom(&self) -> VirtAddr {
+        self.bottom
+    }
+
+    /// 스택의 꼭대기. 비어 있는 스택의 rsp 값이며 TSS에 이 값을 넣습니다.
+    pub fn top(&self) -> VirtAddr {
+        self.top
+    }
+
+    pub fn guard_page(&self) -> Page<Size4KiB> {
+        self.guard
+    }
+
+    pub fn contains(&self, address: VirtAddr) -> bool {
+        self.bottom <= address && address < self.top
+    }
+
+    pub fn size(&self) -> usize {
+        (self.top - self.bottom) as usize
+    }
+
+    /// 지금까지 가장 깊이 쓴 양 (바이트). 패턴을 덮어쓴 가장 낮은 주소부터 꼭대기까지의 크기입니다.
+    pub fn peak_usage(&self) -> usize {
+        let untouched = unsafe { untouched_bytes(self.bottom, self.top) };
+        self.size() - untouched
+    }
+}
+
+// [start, end) 영역을 패턴으로 채웁니다. 컴파일러가 없애지 않도록 volatile로 씁니다.
+unsafe fn fill(start: VirtAddr, end: VirtAddr) {
+    let mut address = start.align_up(8u64);
+    while address + 8u64 <= end {
+        ptr::write_volatile(address.as_mut_ptr::<u64>(), FILL_PATTERN);
+        address += 8u64;
+    }
+}
+
+// 바닥부터 위로 올라가며 패턴이 그대로 남아 있는 바이트 수를 셉니다.
+unsafe fn untouched_bytes(bottom: VirtAddr, top: VirtAddr) -> usize {
+    let mut address = bottom.align_up(8u64);
+    while address + 8u64 <= top && ptr::read_volatile(address.as_ptr::<u64>()) == FILL_PATTERN {
+        address += 8u64;
+    }
+    (address - bottom) as usize
+}
+
+static STACKS: Mutex<[Option<Stack>; MAX_STACKS]> = Mutex::new([None; MAX_STACKS]);
+
+/*
+    부트로더의 커널 스택
+
+    Cargo.toml의 [package.metadata.bootloader]에서 위치와 크기를 정해 두었으므로 아래 값과 같아야 합니다.
+    부트로더는 첫 페이지를 매핑하지 않은 guard page로 남기고 그 위에 kernel-stack-size 페이지를 스택으로 매핑합니다.
+    그래서 스택의 꼭대기는 guard page부터 BOOT_STACK_PAGES + 1 페이지 위입니다.
+*/
+const BOOT_STACK_ADDRESS: u64 = 0xFFFF_FF80_0000_0000;
+const BOOT_STACK_PAGES: u64 = 128;
+
+fn register(stack: Stack) {
+    let mut stacks = STACKS.lock();
+    let slot = stacks.iter_mut().find(|slot| slot.is_none()).expect("too many kernel stacks");
+    *slot = Some(stack);
+}
+
+/*
+    부트로더가 만든 커널 스택을 등록합니다. 다른 스택보다 먼저 호출합니다.
+    이미 쓰고 있는 스택이므로 지금 rsp보다 아래만 패턴으로 채웁니다. 그 위는 처음부터 쓴 것으로 계산됩니다.
+    (커널 타깃은 red zone을 쓰지 않으므로 rsp 아래에는 살아 있는 값이 없습니다.)
+*/
+pub fn init() {
+    let guard = Page::containing_address(VirtAddr::new(BOOT_STACK_ADDRESS));
+    let bottom = guard.start_address() + PAGE_SIZE;
+    let stack = Stack {
+        name: "kernel",
+        guard,
+        bottom,
+        top: guard.start_address() + (BOOT_STACK_PAGES + 1) * PAGE_SIZE as u64,
+    };
+    let rsp: u64;
+    unsafe {
+        asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+    }
+    // fill이 쓰는 스택 프레임은 rsp 위에 있지만, 혹시 모를 호출을 위해 여유를 둡니다.
+    if stack.contains(VirtAddr::new(rsp)) {
+        unsafe { fill(bottom, VirtAddr::new(rsp) - 256u64) };
+    }
+    register(stack);
+}
+
+/// guard page 하나와 `pages` 페이지 크기의 스택을 잘라 줍니다. 영역이 모자라면 부팅을 계속할 수 없으므로 panic합니다.
+pub fn allocate(name: &'static str, pages: usize) -> Stack {
+    let first = NEXT_PAGE.fetch_add(pages + 1, Ordering::Relaxed);
+    let end = first + pages + 1;
+    assert!(end <= AREA_PAGES, "no room left for the {} stack", name);
+
+    let area = VirtAddr::from_ptr(unsafe { AREA.0.as_ptr() });
+    let guard = Page::containing_address(area + first * PAGE_SIZE);
+    let bottom = guard.start_address() + PAGE_SIZE;
+    let stack = Stack { name, guard, bottom, top: bottom + pages * PAGE_SIZE };
+    unsafe { fill(stack.bottom, stack.top) };
+    register(stack);
+    stack
+}
+
+/// 지금까지 만든 스택
+pub fn stacks() -> [Option<Stack>; MAX_STACKS] {
+    *STACKS.lock()
+}
+
+/// `address`가 어떤 스택의 guard page 안에 있으면 그 스택을 돌려줍니다.
+pub fn guarded_by(address: VirtAddr) -> Option<Stack> {
+    let page = Page::<Size4KiB>::containing_address(address);
+    STACKS.lock().iter().flatten().find(|stack| stack.guard == page).copied()
+}
+
+/// 모든 스택의 guard page 매핑을 지웁니다. (부트로더의 커널 스택은 처음부터 지워져 있습니다.) 이후로 스택이 넘치면 .bss를 덮어쓰는 대신 page fault가 발생합니다.
+/// BootInfo로 페이지 테이블을 만드는 `memory::init`이 부릅니다.
+///
+/// # Safety
+/// `mapper`는 현재 사용 중인 페이지 테이블이어야 합니다.
+pub unsafe fn unmap_guard_pages(mapper: &mut impl Mapper<Size4KiB>) -> Result<(), UnmapError> {
+    for stack in STACKS.lock().iter().flatten() {
+        match mapper.unmap(stack.guard) {
+            Ok((_, flush)) => flush.flush(),
+            // 이미 지워진 guard page
+            Err(UnmapError::PageNotMapped) => {}
+            Err(error) => return Err(error),
+        }
+    }
+    GUARDS_UNMAPPED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// guard page가 실제로 매핑에서 지워졌는지. 그 전에는 넘친 스택을 막지 못합니다.
+pub fn guards_active() -> bool {
+    GUARDS_UNMAPPED.load(Ordering::Relaxed)
+}
+
+/// 스택마다 크기와 최대 사용량을 보여주는 표. `println!("{}", report())`처럼 원하는 곳에 출력합니다.
+pub struct Report;
+
+pub fn report() -> Report {
+    Report
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !guards_active() {
+            writeln!(f, "(guard pages are still mapped; stack overflows are not caught)")?;
+        }
+        writeln!(f, "STACK               SIZE       PEAK     %")?;
+        for stack in stacks().iter().flatten() {
+            let peak = stack.peak_usage();
+            writeln!(
+                f,
+                "{:16} {:7} {:10} {:5}",
+                stack.name(),
+                stack.size(),
+                peak,
+                peak * 100 / stack.size(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_stacks_have_guard_pages() {
+    let stacks = stacks();
+    let mut stacks = stacks.iter().flatten();
+    let first = stacks.next().expect("init registers the boot stack");
+    assert_eq!(first.guard_page().start_address() + PAGE_SIZE, first.bottom());
+    assert!(first.top().is_aligned(16u64));
+    assert_eq!(guarded_by(first.bottom() - 1u64), Some(*first));
+    assert_eq!(guarded_by(first.bottom()), None);
+    assert!(!first.contains(first.top()));
+    assert_eq!(first.size(), BOOT_STACK_PAGES as usize * PAGE_SIZE);
+}
+
+#[test_case]
+fn test_boot_stack_contains_rsp() {
+    let rsp: u64;
+    unsafe {
+        asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+    }
+    let boot = stacks()[0].expect("init registers the boot stack");
+    assert!(boot.contains(VirtAddr::new(rsp)));
+    // init이 rsp 아래를 패턴으로 채웠으므로 최대 사용량은 스택 전체보다 작습니다.
+    assert!(boot.peak_usage() < boot.size());
+}
+
+#[test_case]
+fn test_peak_usage_finds_deepest_write() {
+    let mut area = [0u64; 64];
+    let bottom = VirtAddr::from_ptr(area.as_ptr());
+    let top = bottom + core::mem::size_of_val(&area);
+    unsafe { fill(bottom, top) };
+    assert_eq!(unsafe { untouched_bytes(bottom, top) }, 64 * 8);
+
+    // 가장 낮은 곳에 쓴 값까지가 사용한 양입니다. 그 위에 패턴이 남아 있어도 상관없습니다.
+    area[40] = 0;
+    area[20] = 1;
+    assert_eq!(unsafe { untouched_bytes(bottom, top) }, 20 * 8);
+}
+
+#[test_case]
+fn test_guard_pages_are_unmapped() {
+    // 테스트 커널은 init_memory까지 마친 뒤에 테스트를 돌립니다.
+    assert!(guards_active());
+    for stack in stacks().iter().flatten() {
+        assert_eq!(crate::memory::translate_addr(stack.guard_page().start_address()), None, "{}", stack.name());
+        assert!(crate::memory::translate_addr(stack.bottom()).is_some(), "{}", stack.name());
+    }
+}
@@ -0,0 +1,289 @@
+//! 시스템 콜
+//!
+//! 유저 프로그램은 SYSCALL 명령이나 `int 0x80`으로 커널의 기능을 요청합니다. 두 방법의 ABI는 같습니다.
+//! - rax: 시스템 콜 번호 (`number` 모듈)
+//! - rdi, rsi, rdx, r10, r8, r9: 인자 1~6 (SYSCALL이 rcx를 덮어쓰므로 네 번째 인자는 r10으로 넘깁니다.)
+//! - rax: 반환 값. 실패하면 `SyscallError` 코드의 음수를 돌려줍니다.
+//! - SYSCALL은 rcx와 r11을 덮어쓰고, 나머지 레지스터는 보존됩니다.
+//! 이미 배포된 프로그램이 계속 동작하도록 번호와 오류 코드는 바꾸지 않고 새 항목만 추가합니다.
+use core::arch::global_asm;
+use core::str;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::idt::InterruptDescriptorTable;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::{PrivilegeLevel, VirtAddr};
+use crate::monitor::{trap_entry, TrapFrame};
+use crate::{gdt, interrupt_stats, memory, serial, time, usermode, vga_buffer};
+
+pub const INT_VECTOR: u8 = 0x80;
+
+/// 시스템 콜 번호
+pub mod number {
+    /// exit(code) -> !: 유저 프로그램을 끝냅니다.
+    pub const EXIT: u64 = 0;
+    /// write(fd, buffer, len) -> 쓴 바이트 수. fd 1은 화면, 2는 시리얼 포트입니다.
+    pub const WRITE: u64 = 1;
+    /// get_time() -> 부팅 후 지난 시간 (나노초)
+    pub const GET_TIME: u64 = 2;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum SyscallError {
+    /// 없는 시스템 콜 번호
+    NoSuchSyscall = 1,
+    /// 유저 주소 공간 밖을 가리키는 포인터
+    BadAddress = 2,
+    /// 잘못된 인자 (UTF-8이 아닌 문자열 등)
+    InvalidArgument = 3,
+    /// 없는 파일 디스크립터
+    BadDescriptor = 4,
+}
+
+// 하위 절반(canonical lower half)이 유저 주소 공간입니다.
+const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+const PAGE_SIZE: u64 = 4096;
+// write 한 번에 쓸 수 있는 최대 길이. 더 길면 앞부분만 쓰고 쓴 길이를 돌려줍니다.
+const MAX_WRITE: u64 = 4096;
+
+const STDOUT: u64 = 1;
+const STDERR: u64 = 2;
+
+fn encode(result: Result<u64, SyscallError>) -> u64 {
+    match result {
+        Ok(value) => value,
+        Err(error) => (error as u64).wrapping_neg(),
+    }
+}
+
+fn dispatch(number: u64, args: [u64; 6]) -> Result<u64, SyscallError> {
+    match number {
+        number::EXIT => unsafe { usermode::exit_to_kernel(args[0]) },
+        number::WRITE => write(args[0], args[1], args[2]),
+        number::GET_TIME => Ok(time::uptime().as_nanos() as u64),
+        _ => Err(SyscallError::NoSuchSyscall),
+    }
+}
+
+/*
+    유저가 넘긴 포인터는 읽기 전에 페이지 테이블에서 범위 안의 모든 페이지를 확인합니다.
+    주소 범위만 보면 안 됩니다. 부트로더는 커널과 물리 메모리 매핑도 하위 절반에 올리므로, 그대로 읽으면
+    유저 프로그램이 커널 메모리를 화면에 출력할 수 있습니다. 매핑되지 않은 주소를 읽으면 커널에서 page fault가 납니다.
+    그래서 모든 페이지가 PRESENT이고 USER_ACCESSIBLE이어야만 읽습니다.
+*/
+fn check_user_buffer(buffer: u64, len: u64) -> Result<&'static [u8], SyscallError> {
+    let end = buffer.checked_add(len).ok_or(SyscallError::BadAddress)?;
+    if buffer == 0 || end > USER_SPACE_END {
+        return Err(SyscallError::BadAddress);
+    }
+    let required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    let mut page = buffer & !(PAGE_SIZE - 1);
+    while page < end {
+        match memory::translate(VirtAddr::new(page)) {
+            Ok((_, flags)) if flags.contains(required) => page += PAGE_SIZE,
+            _ => return Err(SyscallError::BadAddress),
+        }
+    }
+    Ok(unsafe { core::slice::from_raw_parts(buffer as *const u8, len as usize) })
+}
+
+// 버퍼가 UTF-8 문자의 중간에서 끝나면(MAX_WRITE로 자른 경우 포함) 그 앞까지만 씁니다.
+// 유저 프로그램은 돌려받은 길이를 보고 나머지를 다시 쓰면 됩니다.
+fn utf8_prefix(bytes: &[u8]) -> Result<&str, SyscallError> {
+    match str::from_utf8(bytes) {
+        Ok(text) => Ok(text),
+        Err(error) if error.error_len().is_none() => {
+            str::from_utf8(&bytes[..error.valid_up_to()]).map_err(|_| SyscallError::InvalidArgument)
+        }
+        Err(_) => Err(SyscallError::InvalidArgument),
+    }
+}
+
+fn write(fd: u64, buffer: u64, len: u64) -> Result<u64, SyscallError> {
+    let bytes = check_user_buffer(buffer, len.min(MAX_WRITE))?;
+    let text = utf8_prefix(bytes)?;
+    match fd {
+        STDOUT => vga_buffer::_print(format_args!("{}", text)),
+        STDERR => serial::_print(format_args!("{}", text)),
+        _ => return Err(SyscallError::BadDescriptor),
+    }
+    Ok(text.len() as u64)
+}
+
+fn handle(frame: &mut TrapFrame) {
+    let r = &frame.registers;
+    let result = dispatch(r.rax, [r.rdi, r.rsi, r.rdx, r.r10, r.r8, r.r9]);
+    frame.registers.rax = encode(result);
+}
+
+/*
+    int 0x80
+
+    IDT 항목의 DPL을 3으로 해야 유저 모드에서 int 명령으로 부를 수 있습니다. (DPL이 0이면 #GP가 발생합니다.)
+    CPU가 TSS의 privilege_stack_table[0]으로 스택을 바꿔 주므로 monitor 모듈의 진입 코드를 그대로 씁니다.
+*/
+trap_entry!("syscall_int80_entry", 0x80, "syscall_int80");
+
+#[no_mangle]
+extern "C" fn syscall_int80(frame: &mut TrapFrame, _vector: u64) {
+    let entry = interrupt_stats::enter();
+    handle(frame);
+    interrupt_stats::record(INT_VECTOR, entry);
+}
+
+/*
+    SYSCALL / SYSRET
+
+    SYSCALL은 rip를 rcx에, RFLAGS를 r11에 저장하고 STAR의 커널 셀렉터와 LSTAR의 주소로 이동할 뿐 스택은 바꾸지 않습니다.
+    그래서 진입 코드가 직접 유저 rsp를 저장하고 커널 스택으로 바꾼 뒤, int 0x80과 같은 모양의 TrapFrame을 만듭니다.
+    (프레임의 cs와 ss는 gdt 모듈의 배치에 따른 값이며, SYSRET은 STAR로 셀렉터를 다시 계산하므로 보여주기만 합니다.)
+    SFMASK로 인터럽트를 끈 채 들어오므로, 스택을 바꾸기 전에 인터럽트가 끼어들 수 없습니다.
+    CPU가 하나뿐이므로 swapgs 대신 전역 변수에 rsp를 저장합니다.
+*/
+#[no_mangle]
+static mut SYSCALL_KERNEL_RSP: u64 = 0;
+#[no_mangle]
+static mut SYSCALL_USER_RSP: u64 = 0;
+
+global_asm!(
+    ".global syscall_entry",
+    "syscall_entry:",
+    "    mov [rip + SYSCALL_USER_RSP], rsp",
+    "    mov rsp, [rip + SYSCALL_KERNEL_RSP]",
+    "    push 0x1b",
+    "    push qword ptr [rip + SYSCALL_USER_RSP]",
+    "    push r11",
+    "    push 0x23",
+    "    push rcx",
+    "    push rax",
+    "    push rbx",
+    "    push rcx",
+    "    push rdx",
+    "    push rsi",
+    "    push rdi",
+    "    push rbp",
+    "    push r8",
+    "    push r9",
+    "    push r10",
+    "    push r11",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov rdi, rsp",
+    "    cld",
+    "    call syscall_handler",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop r11",
+    "    pop r10",
+    "    pop r9",
+    "    pop r8",
+    "    pop rbp",
+    "    pop rdi",
+    "    pop rsi",
+    "    pop rdx",
+    "    pop rcx",
+    "    pop rbx",
+    "    pop rax",
+    // 핸들러가 바꾼 rip와 RFLAGS로 돌아갑니다.
+    "    pop rcx",
+    "    add rsp, 8",
+    "    pop r11",
+    "    pop rsp",
+    "    sysretq",
+);
+
+extern "C" {
+    fn syscall_entry();
+    fn syscall_int80_entry();
+}
+
+#[no_mangle]
+extern "C" fn syscall_handler(frame: &mut TrapFrame) {
+    handle(frame);
+    /*
+        SYSRET은 rcx가 canonical 주소가 아니면 ring 0에서 #GP를 일으키는데, 그때 rsp는 이미 유저의 값입니다.
+        하위 절반의 마지막 바이트 바로 앞에서 SYSCALL을 실행하면 이렇게 될 수 있으므로 그런 프로그램은 끝냅니다.
+    */
+    if frame.stack_frame.instruction_pointer.as_u64() >= USER_SPACE_END {
+        unsafe { usermode::exit_to_kernel(encode(Err(SyscallError::BadAddress))) };
+    }
+}
+
+/// `int 0x80` 게이트를 유저 모드에서 호출할 수 있게 등록합니다.
+pub fn register(idt: &mut InterruptDescriptorTable) {
+    unsafe {
+        idt[usize::from(INT_VECTOR)]
+            .set_handler_addr(VirtAddr::new(syscall_int80_entry as usize as u64))
+            .set_privilege_level(PrivilegeLevel::Ring3);
+    }
+}
+
+/// SYSCALL 명령을 켜고 진입 코드를 등록합니다. `gdt::init` 다음에 호출해야 합니다.
+pub fn init() {
+    let selectors = gdt::selectors();
+    // 진입 코드가 TrapFrame에 넣는 셀렉터 값은 GDT의 배치와 같아야 합니다.
+    debug_assert_eq!((selectors.user_data_selector.0, selectors.user_code_selector.0), (0x1b, 0x23));
+    Star::write(
+        selectors.user_code_selector,
+        selectors.user_data_selector,
+        selectors.code_selector,
+        selectors.data_selector,
+    ).expect("GDT layout does not match what SYSRET expects");
+    LStar::write(VirtAddr::new(syscall_entry as usize as u64));
+    // 커널에 들어올 때 인터럽트, 트랩, 방향, 정렬 검사 플래그를 끕니다.
+    SFMask::write(
+        RFlags::INTERRUPT_FLAG | RFlags::TRAP_FLAG | RFlags::DIRECTION_FLAG | RFlags::ALIGNMENT_CHECK,
+    );
+    unsafe {
+        SYSCALL_KERNEL_RSP = gdt::privilege_stack_top().as_u64();
+        Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS));
+    }
+}
+
+#[test_case]
+fn test_dispatch_errors_and_time() {
+    assert_eq!(dispatch(0xFFFF, [0; 6]), Err(SyscallError::NoSuchSyscall));
+    assert_eq!(encode(Err(SyscallError::NoSuchSyscall)) as i64, -1);
+    // 상위 절반, 하위 절반에 있는 커널의 데이터, 매핑되지 않은 주소는 모두 거부합니다.
+    assert_eq!(dispatch(number::WRITE, [STDOUT, 0xFFFF_8000_0000_0000, 4, 0, 0, 0]), Err(SyscallError::BadAddress));
+    let message = b"ok";
+    assert_eq!(dispatch(number::WRITE, [9, message.as_ptr() as u64, 2, 0, 0, 0]), Err(SyscallError::BadAddress));
+    assert_eq!(dispatch(number::WRITE, [STDOUT, 0xdeadbeaf000, 4, 0, 0, 0]), Err(SyscallError::BadAddress));
+    assert!(dispatch(number::GET_TIME, [0; 6]).unwrap() > 0);
+}
+
+#[test_case]
+fn test_write_checks_every_page() {
+    use x86_64::structures::paging::Page;
+
+    // 유저 페이지 바로 뒤의 페이지는 매핑되어 있지 않습니다.
+    let page = Page::containing_address(VirtAddr::new(0x2222_2222_0000));
+    let frame = memory::map(page, PageTableFlags::USER_ACCESSIBLE).unwrap();
+    let start = page.start_address().as_u64();
+    assert_eq!(dispatch(number::WRITE, [9, start + 4000, 96, 0, 0, 0]), Err(SyscallError::BadDescriptor));
+    assert_eq!(dispatch(number::WRITE, [9, start + 4000, 97, 0, 0, 0]), Err(SyscallError::BadAddress));
+
+    // 커널 전용 페이지는 매핑되어 있어도 거부합니다.
+    unsafe { memory::protect(page, PageTableFlags::empty()).unwrap() };
+    assert_eq!(dispatch(number::WRITE, [9, start, 1, 0, 0, 0]), Err(SyscallError::BadAddress));
+
+    memory::unmap(page).unwrap();
+    unsafe {
+        use x86_64::structures::paging::FrameDeallocator;
+        crate::frame_allocator::BitmapFrameAllocator.deallocate_frame(frame);
+    }
+}
+
+#[test_case]
+fn test_write_stops_before_a_cut_character() {
+    // "가"는 UTF-8로 3바이트입니다.
+    let bytes = "ab가".as_bytes();
+    assert_eq!(utf8_prefix(bytes), Ok("ab가"));
+    assert_eq!(utf8_prefix(&bytes[..4]), Ok("ab"));
+    assert_eq!(utf8_prefix(&[b'a', 0xFF, b'b']), Err(SyscallError::InvalidArgument));
+}
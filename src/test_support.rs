@@ -0,0 +1,57 @@
+//! 테스트에서 같이 쓰는 도구
+//!
+//! 힙이 없는 테스트 커널에서도 panic 메시지나 `Display` 출력을 검사할 수 있도록 고정 크기 버퍼에 글자를 모읍니다.
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use crate::{exit_qemu, serial_println, QemuExitCode};
+
+const CAPACITY: usize = 512;
+
+/// `fmt::Write`로 받은 글자를 담는 고정 크기 버퍼. 넘치는 부분은 버립니다.
+pub struct Message {
+    bytes: [u8; CAPACITY],
+    len: usize,
+}
+
+impl Message {
+    pub const fn new() -> Message {
+        Message { bytes: [0; CAPACITY], len: 0 }
+    }
+
+    /// `value`를 `Display`로 출력한 결과를 담습니다.
+    pub fn of(value: &dyn fmt::Display) -> Message {
+        let mut message = Message::new();
+        let _ = write!(message, "{}", value);
+        message
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    pub fn contains(&self, needle: &str) -> bool {
+        self.as_bytes().windows(needle.len()).any(|window| window == needle.as_bytes())
+    }
+}
+
+impl Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = (self.len + s.len()).min(self.bytes.len());
+        self.bytes[self.len..end].copy_from_slice(&s.as_bytes()[..end - self.len]);
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// panic이 일어나야 성공하는 테스트의 panic 핸들러에서 부릅니다.
+/// 메시지에 `needles`가 모두 들어 있으면 성공, 아니면 메시지를 출력하고 실패로 QEMU를 끝냅니다.
+pub fn expect_panic(info: &PanicInfo, needles: &[&str]) -> ! {
+    let message = Message::of(info);
+    if needles.iter().all(|needle| message.contains(needle)) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    }
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}\n", info);
+    exit_qemu(QemuExitCode::Failed);
+}
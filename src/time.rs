@@ -0,0 +1,77 @@
+//! 시간
+//!
+//! PIT 채널 0을 원하는 주파수로 설정하고, IRQ0이 들어올 때마다 틱 카운터를 하나씩 올립니다.
+//! 부팅 후 지난 시간(uptime)과 sleep은 모두 이 카운터를 기준으로 합니다.
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use core::time::Duration;
+use crate::interrupts::{self, InterruptIndex, IrqError};
+use crate::{pit, timer, workqueue};
+
+/// 1000 Hz면 틱 하나가 약 1ms입니다.
+pub const DEFAULT_FREQUENCY: u32 = 1000;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+// 틱의 실제 길이는 분주값으로 정해집니다. init 전에는 전원이 켜졌을 때의 값(65536)입니다.
+static DIVISOR: AtomicU32 = AtomicU32::new(0x10000);
+
+/// PIT를 `frequency` Hz에 가장 가까운 주파수로 설정하고 IRQ0 핸들러를 등록합니다.
+pub fn init(frequency: u32) -> Result<(), IrqError> {
+    let divisor = pit::divisor_for(frequency);
+    DIVISOR.store(divisor, Ordering::Relaxed);
+    pit::set_channel_0_divisor(divisor);
+    interrupts::register_irq(InterruptIndex::Timer.irq(), handle_tick)
+}
+
+fn handle_tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    timer::tick(now);
+}
+
+/// 부팅 후 발생한 타이머 인터럽트의 수
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// 실제 타이머 주파수 (Hz). 분주값이 정수이므로 요청한 값과 조금 다를 수 있습니다.
+pub fn frequency() -> u32 {
+    pit::BASE_FREQUENCY / DIVISOR.load(Ordering::Relaxed)
+}
+
+fn ticks_to_duration(ticks: u64) -> Duration {
+    let divisor = u128::from(DIVISOR.load(Ordering::Relaxed));
+    let nanos = u128::from(ticks) * divisor * NANOS_PER_SECOND / u128::from(pit::BASE_FREQUENCY);
+    Duration::from_nanos(nanos as u64)
+}
+
+/// 타이머를 설정한 뒤로 지난 시간. 해상도는 틱 하나의 길이입니다.
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+/// `duration`이 지날 때까지 CPU를 멈추고 기다립니다.
+/// 틱 단위로 올림하므로 최소한 `duration`만큼은 기다립니다.
+pub fn sleep(duration: Duration) {
+    // 인터럽트가 꺼져 있으면 hlt에서 영원히 깨어나지 못합니다.
+    debug_assert!(x86_64::instructions::interrupts::are_enabled());
+
+    /*
+        uptime은 틱 단위로만 증가하므로 지금 틱이 이미 거의 끝났을 수도 있습니다.
+        그래서 한 틱을 더 기다려 요청한 시간보다 짧게 자지 않도록 합니다.
+        검사와 hlt 사이에 타이머 인터럽트가 끼어들어도 다음 틱에 다시 깨어나므로,
+        input::wait와 달리 인터럽트를 끄고 검사할 필요는 없습니다.
+        자는 동안 들어온 작업(타이머 콜백, 키 입력 해석)은 workqueue::idle이 실행합니다.
+    */
+    let deadline = uptime() + duration + ticks_to_duration(1);
+    while uptime() < deadline {
+        workqueue::idle();
+    }
+}
+
+#[test_case]
+fn test_sleep_advances_uptime() {
+    let start = uptime();
+    sleep(Duration::from_millis(20));
+    assert!(uptime() - start >= Duration::from_millis(20));
+}
@@ -0,0 +1,278 @@
+//! 소프트웨어 타이머
+//!
+//! 일정 시간 뒤에 한 번(`after`), 또는 일정 주기마다(`every`) 함수를 호출합니다.
+//! 타이머는 해시 타이밍 휠(hashed timing wheel)에 보관합니다. 만료 틱을 WHEEL_SIZE로 나눈 나머지 칸에 넣어 두면,
+//! 틱마다 해당하는 한 칸만 살펴보면 되므로 타이머 수와 상관없이 틱 처리 비용이 일정합니다.
+//!
+//! 타이머 인터럽트 핸들러는 만료된 타이머를 작업 큐(workqueue)에 넣기만 하고, 콜백은 인터럽트가 켜진 상태에서
+//! 작업 큐가 실행될 때(유휴 루프 등) 호출됩니다. 그래서 콜백 안에서 오래 걸리는 일을 해도 다른 인터럽트를 막지 않습니다.
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::{time, workqueue};
+
+pub const MAX_TIMERS: usize = 64;
+const WHEEL_SIZE: usize = 64;
+
+pub type TimerCallback = fn();
+
+/// 타이머를 가리키는 값. 취소된 타이머의 슬롯이 재사용되어도 세대(generation)가 달라서 구분됩니다.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId {
+    slot: u8,
+    generation: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerError {
+    /// 모든 타이머 슬롯이 사용 중입니다.
+    NoFreeSlot,
+    /// 이미 만료되었거나 취소된 타이머입니다.
+    NotFound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Free,
+    // 휠에 들어 있고 아직 만료되지 않았습니다. (주기 타이머는 만료된 뒤에도 다시 이 상태가 됩니다.)
+    Armed,
+    // 한 번만 실행되는 타이머가 만료되어 콜백 실행을 기다리고 있습니다.
+    Expired,
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+    state: State,
+    generation: u32,
+    deadline: u64,
+    // 0이면 한 번만 실행됩니다.
+    period: u64,
+    callback: Option<TimerCallback>,
+    // 같은 칸에 있는 다음 타이머
+    next: Option<u8>,
+}
+
+const FREE_SLOT: Slot = Slot {
+    state: State::Free,
+    generation: 0,
+    deadline: 0,
+    period: 0,
+    callback: None,
+    next: None,
+};
+
+struct Wheel {
+    slots: [Slot; MAX_TIMERS],
+    buckets: [Option<u8>; WHEEL_SIZE],
+}
+
+impl Wheel {
+    const fn new() -> Wheel {
+        Wheel {
+            slots: [FREE_SLOT; MAX_TIMERS],
+            buckets: [None; WHEEL_SIZE],
+        }
+    }
+
+    fn bucket(deadline: u64) -> usize {
+        (deadline % WHEEL_SIZE as u64) as usize
+    }
+
+    fn link(&mut self, slot: u8) {
+        let bucket = Wheel::bucket(self.slots[usize::from(slot)].deadline);
+        self.slots[usize::from(slot)].next = self.buckets[bucket];
+        self.buckets[bucket] = Some(slot);
+    }
+
+    fn unlink(&mut self, slot: u8) {
+        let bucket = Wheel::bucket(self.slots[usize::from(slot)].deadline);
+        let next = self.slots[usize::from(slot)].next;
+        if self.buckets[bucket] == Some(slot) {
+            self.buckets[bucket] = next;
+            return;
+        }
+        let mut current = self.buckets[bucket];
+        while let Some(index) = current {
+            if self.slots[usize::from(index)].next == Some(slot) {
+                self.slots[usize::from(index)].next = next;
+                return;
+            }
+            current = self.slots[usize::from(index)].next;
+        }
+    }
+
+    fn add(&mut self, deadline: u64, period: u64, callback: TimerCallback) -> Result<TimerId, TimerError> {
+        let index = self.slots.iter().position(|slot| slot.state == State::Free)
+            .ok_or(TimerError::NoFreeSlot)?;
+        let slot = &mut self.slots[index];
+        slot.state = State::Armed;
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.deadline = deadline;
+        slot.period = period;
+        slot.callback = Some(callback);
+        let id = TimerId { slot: index as u8, generation: slot.generation };
+        self.link(id.slot);
+        Ok(id)
+    }
+
+    fn get(&self, id: TimerId) -> Option<&Slot> {
+        self.slots.get(usize::from(id.slot))
+            .filter(|slot| slot.state != State::Free && slot.generation == id.generation)
+    }
+
+    fn cancel(&mut self, id: TimerId) -> Result<(), TimerError> {
+        let state = self.get(id).ok_or(TimerError::NotFound)?.state;
+        if state == State::Armed {
+            self.unlink(id.slot);
+        }
+        self.slots[usize::from(id.slot)].state = State::Free;
+        Ok(())
+    }
+
+    // `now` 틱에 만료되는 타이머를 찾아 `expire`에 넘깁니다.
+    fn advance(&mut self, now: u64, mut expire: impl FnMut(TimerId)) {
+        let mut current = self.buckets[Wheel::bucket(now)];
+        while let Some(index) = current {
+            let slot = self.slots[usize::from(index)];
+            current = slot.next;
+            // 같은 칸에는 WHEEL_SIZE 틱 이후에 만료되는 타이머도 들어 있습니다.
+            if slot.deadline > now {
+                continue;
+            }
+
+            self.unlink(index);
+            expire(TimerId { slot: index, generation: slot.generation });
+            if slot.period == 0 {
+                self.slots[usize::from(index)].state = State::Expired;
+            } else {
+                // 주기 타이머는 처리가 늦어져도 원래 일정에 맞춰 다시 넣습니다.
+                self.slots[usize::from(index)].deadline = slot.deadline + slot.period;
+                self.link(index);
+            }
+        }
+    }
+
+    // 만료된 타이머의 콜백을 꺼냅니다. 한 번만 실행되는 타이머는 이때 슬롯을 돌려줍니다.
+    fn take_callback(&mut self, id: TimerId) -> Option<TimerCallback> {
+        let slot = self.slots.get_mut(usize::from(id.slot))?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        match slot.state {
+            State::Expired => {
+                slot.state = State::Free;
+                slot.callback
+            }
+            State::Armed => slot.callback,
+            State::Free => None,
+        }
+    }
+}
+
+static WHEEL: Mutex<Wheel> = Mutex::new(Wheel::new());
+// 작업 큐가 가득 차서 콜백을 실행하지 못한 만료의 수
+static OVERRUNS: AtomicU64 = AtomicU64::new(0);
+
+// TimerId를 작업 큐의 인자 하나에 담습니다.
+impl TimerId {
+    fn to_usize(self) -> usize {
+        usize::from(self.slot) | ((self.generation as usize) << 8)
+    }
+
+    fn from_usize(value: usize) -> TimerId {
+        TimerId { slot: value as u8, generation: (value >> 8) as u32 }
+    }
+}
+
+// 밀리초를 틱 수로 바꿉니다. 최소 한 틱이며, 요청보다 일찍 실행되지 않도록 올림합니다.
+fn millis_to_ticks(ms: u64) -> u64 {
+    let frequency = u64::from(time::frequency());
+    ((ms * frequency + 999) / 1000).max(1)
+}
+
+fn add(ms: u64, periodic: bool, callback: TimerCallback) -> Result<TimerId, TimerError> {
+    let ticks = millis_to_ticks(ms);
+    without_interrupts(|| {
+        let deadline = time::ticks() + ticks;
+        WHEEL.lock().add(deadline, if periodic { ticks } else { 0 }, callback)
+    })
+}
+
+/// `ms` 밀리초 뒤에 `callback`을 한 번 호출합니다.
+pub fn after(ms: u64, callback: TimerCallback) -> Result<TimerId, TimerError> {
+    add(ms, false, callback)
+}
+
+/// `ms` 밀리초마다 `callback`을 호출합니다.
+pub fn every(ms: u64, callback: TimerCallback) -> Result<TimerId, TimerError> {
+    add(ms, true, callback)
+}
+
+/// 타이머를 취소합니다. 이미 만료되어 실행을 기다리던 콜백도 실행되지 않습니다.
+pub fn cancel(id: TimerId) -> Result<(), TimerError> {
+    without_interrupts(|| WHEEL.lock().cancel(id))
+}
+
+/// 타이머 인터럽트 핸들러가 매 틱마다 호출합니다.
+pub(crate) fn tick(now: u64) {
+    WHEEL.lock().advance(now, |id| {
+        if workqueue::schedule(run_timer, id.to_usize()).is_err() {
+            OVERRUNS.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+}
+
+// 작업 큐에서 실행됩니다. 휠은 잠깐만 잠그고, 콜백은 잠금을 푼 뒤 실행합니다.
+fn run_timer(id: usize) {
+    let callback = without_interrupts(|| WHEEL.lock().take_callback(TimerId::from_usize(id)));
+    if let Some(callback) = callback {
+        callback();
+    }
+}
+
+pub fn overruns() -> u64 {
+    OVERRUNS.load(Ordering::Relaxed)
+}
+
+#[test_case]
+fn test_wheel_one_shot_and_periodic() {
+    fn callback() {}
+    use crate::ring_buffer::RingBuffer;
+
+    let mut wheel = Wheel::new();
+    let mut expired = RingBuffer::<TimerId, MAX_TIMERS>::new();
+    let one_shot = wheel.add(3, 0, callback).unwrap();
+    // 같은 칸(3)에 들어가지만 한 바퀴 뒤에 만료됩니다.
+    let later = wheel.add(3 + WHEEL_SIZE as u64, 0, callback).unwrap();
+    let periodic = wheel.add(2, 2, callback).unwrap();
+
+    wheel.advance(2, |id| expired.push(id).unwrap());
+    assert_eq!(expired.pop(), Some(periodic));
+    wheel.advance(3, |id| expired.push(id).unwrap());
+    assert_eq!(expired.pop(), Some(one_shot));
+    assert!(expired.is_empty());
+    wheel.advance(4, |id| expired.push(id).unwrap());
+    assert_eq!(expired.pop(), Some(periodic));
+
+    assert!(wheel.take_callback(one_shot).is_some());
+    assert_eq!(wheel.cancel(one_shot), Err(TimerError::NotFound));
+    assert_eq!(wheel.cancel(later), Ok(()));
+    assert_eq!(wheel.cancel(periodic), Ok(()));
+    assert!(wheel.slots.iter().all(|slot| slot.state == State::Free));
+}
+
+#[test_case]
+fn test_after_runs_callback() {
+    use core::sync::atomic::AtomicBool;
+    use core::time::Duration;
+
+    static FIRED: AtomicBool = AtomicBool::new(false);
+    fn callback() {
+        FIRED.store(true, Ordering::Relaxed);
+    }
+
+    after(5, callback).unwrap();
+    // sleep이 쉬는 동안 작업 큐를 실행하므로 콜백도 그 사이에 불립니다.
+    time::sleep(Duration::from_millis(20));
+    assert!(FIRED.load(Ordering::Relaxed));
+}
@@ -0,0 +1,167 @@
+//! TSC (Time Stamp Counter)
+//!
+//! TSC는 rdtsc 명령으로 읽는 64비트 카운터로, 읽는 비용이 작고 해상도가 나노초 이하입니다.
+//! 하지만 증가 속도가 CPU마다 다르므로 주파수를 아는 다른 시계(HPET 또는 PIT)와 비교해 보정해야 합니다.
+//! 오래된 CPU에서는 절전 상태나 클럭 변경에 따라 속도가 바뀌기도 하는데,
+//! CPUID가 invariant TSC를 보고하면 항상 일정한 속도로 증가한다고 믿을 수 있습니다.
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
+use crate::hpet;
+use crate::pit;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+const FEMTOSECONDS_PER_SECOND: u128 = 1_000_000_000_000_000;
+
+// 보정에 사용하는 구간의 길이(10ms)와 반복 횟수
+const CALIBRATION_MILLIS: u32 = 10;
+const CALIBRATION_ROUNDS: usize = 3;
+
+// 보정 전에는 0입니다.
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+// 마지막 보정에 쓴 기준 시계. 보정 전에는 NO_SOURCE입니다.
+static SOURCE: AtomicU8 = AtomicU8::new(NO_SOURCE);
+const NO_SOURCE: u8 = u8::MAX;
+
+pub fn read() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// CPUID 0x80000007의 EDX bit 8 (Invariant TSC)
+pub fn is_invariant() -> bool {
+    const CPUID_INVARIANT_TSC: u32 = 1 << 8;
+    unsafe {
+        let max_extended_leaf = core::arch::x86_64::__cpuid(0x8000_0000).eax;
+        max_extended_leaf >= 0x8000_0007
+            && core::arch::x86_64::__cpuid(0x8000_0007).edx & CPUID_INVARIANT_TSC != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ClockSource {
+    Pit,
+    Hpet,
+}
+
+/*
+    보정 방법
+
+    기준 시계로 일정 시간(10ms)을 재는 동안 TSC가 얼마나 증가했는지 세어 주파수를 계산합니다.
+    기준 시계를 읽거나 기다리는 데 드는 시간만큼 측정값이 커지므로, 여러 번 재서 가장 작은 값을 사용합니다.
+    HPET는 카운터를 직접 읽을 수 있어 더 정확하므로, HPET가 초기화되어 있으면 HPET를, 아니면 PIT 채널 2를 사용합니다.
+*/
+fn calibrate_with_pit() -> u64 {
+    let count = pit::BASE_FREQUENCY * CALIBRATION_MILLIS / 1000;
+    let cycles = (0..CALIBRATION_ROUNDS)
+        .map(|_| {
+            let start = read();
+            pit::busy_wait(count as u16);
+            read() - start
+        })
+        .min()
+        .unwrap_or(0);
+    (u128::from(cycles) * u128::from(pit::BASE_FREQUENCY) / u128::from(count)) as u64
+}
+
+fn calibrate_with_hpet(hpet: &hpet::Hpet) -> u64 {
+    let count = hpet.frequency() * u64::from(CALIBRATION_MILLIS) / 1000;
+    let (cycles, elapsed) = (0..CALIBRATION_ROUNDS)
+        .map(|_| {
+            let (start, start_counter) = (read(), hpet.counter());
+            let mut counter = start_counter;
+            while counter.wrapping_sub(start_counter) < count {
+                counter = hpet.counter();
+            }
+            (read() - start, counter.wrapping_sub(start_counter))
+        })
+        .min()
+        .unwrap_or((0, 1));
+    let elapsed_fs = u128::from(elapsed) * u128::from(hpet.period_fs());
+    (u128::from(cycles) * FEMTOSECONDS_PER_SECOND / elapsed_fs) as u64
+}
+
+/// TSC 주파수를 측정해 저장하고, 측정한 주파수(Hz)와 기준 시계를 돌려줍니다.
+pub fn calibrate() -> (u64, ClockSource) {
+    let (frequency, source) = match hpet::hpet() {
+        Some(hpet) => (calibrate_with_hpet(hpet), ClockSource::Hpet),
+        None => (calibrate_with_pit(), ClockSource::Pit),
+    };
+    FREQUENCY.store(frequency, Ordering::Relaxed);
+    SOURCE.store(source as u8, Ordering::Relaxed);
+    (frequency, source)
+}
+
+/// 마지막으로 보정할 때 기준으로 삼은 시계
+pub fn clock_source() -> Option<ClockSource> {
+    match SOURCE.load(Ordering::Relaxed) {
+        source if source == ClockSource::Pit as u8 => Some(ClockSource::Pit),
+        source if source == ClockSource::Hpet as u8 => Some(ClockSource::Hpet),
+        _ => None,
+    }
+}
+
+/// 보정된 TSC 주파수 (Hz)
+pub fn frequency() -> Option<u64> {
+    match FREQUENCY.load(Ordering::Relaxed) {
+        0 => None,
+        frequency => Some(frequency),
+    }
+}
+
+/// TSC 값을 시간으로 바꿉니다. 보정 전에는 항상 0입니다.
+pub fn cycles_to_duration(cycles: u64) -> Duration {
+    match frequency() {
+        Some(frequency) => {
+            Duration::from_nanos((u128::from(cycles) * NANOS_PER_SECOND / u128::from(frequency)) as u64)
+        }
+        None => Duration::ZERO,
+    }
+}
+
+/// TSC로 잰 시각. 나노초 해상도로 두 시각 사이의 시간을 계산할 수 있습니다.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Instant {
+        Instant(read())
+    }
+
+    /// `earlier`가 더 나중의 시각이면 0을 돌려줍니다.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        cycles_to_duration(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.0
+    }
+}
+
+#[test_case]
+fn test_instant_measures_sleep() {
+    let start = Instant::now();
+    crate::time::sleep(Duration::from_millis(10));
+    let elapsed = start.elapsed();
+    // PIT와 TSC의 오차를 감안해 넉넉하게 비교합니다.
+    assert!(elapsed >= Duration::from_millis(5), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+}
+
+#[test_case]
+fn test_calibrated_against_hpet() {
+    // 테스트 커널도 init_memory에서 HPET를 켜고 다시 보정합니다.
+    let hpet = hpet::hpet().expect("HPET is not initialized");
+    assert_eq!(clock_source(), Some(ClockSource::Hpet));
+
+    // HPET로 20ms를 재는 동안 TSC로 잰 시간도 5% 안에서 같아야 합니다. (에뮬레이터에서 HPET를 읽는 비용을 감안합니다.)
+    let count = hpet.frequency() / 50;
+    let (start, start_counter) = (Instant::now(), hpet.counter());
+    while hpet.counter().wrapping_sub(start_counter) < count {}
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(19), "{:?}", elapsed);
+    assert!(elapsed <= Duration::from_millis(21), "{:?}", elapsed);
+}
@@ -0,0 +1,142 @@
+//! 유저 모드(ring 3) 진입
+//!
+//! 유저 모드의 코드는 특권 명령(hlt, cli, 포트 입출력 등)을 실행할 수 없고 USER_ACCESSIBLE 페이지에만 접근할 수 있으므로,
+//! 믿을 수 없는 프로그램을 커널과 분리해서 실행할 수 있습니다.
+//!
+//! 유저 모드로 가는 방법은 인터럽트에서 돌아가는 것처럼 iretq를 실행하는 것입니다. 스택에 유저 코드와 스택의 셀렉터,
+//! 돌아갈 주소를 쌓아 두고 iretq를 실행하면 CPU가 특권 수준을 3으로 바꾸며 그 주소로 이동합니다.
+//! 유저 프로그램이 인터럽트나 시스템 콜로 커널에 들어오면 CPU는 TSS의 privilege_stack_table[0] 스택으로 바꿉니다.
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use x86_64::instructions::segmentation::{Segment, DS, ES};
+use x86_64::VirtAddr;
+use crate::gdt;
+
+/*
+    usermode_enter(entry, user_stack, user_code_selector, user_data_selector, saved_rsp)
+
+    호출한 함수에게 보존해야 하는 레지스터(rbx, rbp, r12~r15)와 RFLAGS를 커널 스택에 저장하고, 그때의 rsp를 saved_rsp에 적습니다.
+    그 다음 iretq용 프레임(ss, rsp, rflags, cs, rip)을 쌓고, 커널의 값이 새어 나가지 않도록 범용 레지스터를 지운 뒤 iretq합니다.
+    유저 모드의 RFLAGS는 0x202입니다. 인터럽트를 켜고(IF) 항상 1인 1번 비트를 세웁니다.
+    IOPL은 0이므로 유저 코드는 포트에 접근할 수 없습니다.
+
+    usermode_exit(saved_rsp, code)
+
+    저장해 둔 rsp로 돌아가서 레지스터를 복원하고 ret하므로, usermode_enter가 `code`를 돌려주며 끝난 것처럼 됩니다.
+    유저 프로그램이 들어온 커널 스택(privilege_stack_table[0])에 남은 내용은 버려집니다.
+*/
+global_asm!(
+    ".global usermode_enter",
+    "usermode_enter:",
+    "    push rbx",
+    "    push rbp",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    pushfq",
+    "    mov [r8], rsp",
+    "    push rcx",
+    "    push rsi",
+    "    push 0x202",
+    "    push rdx",
+    "    push rdi",
+    "    mov ds, cx",
+    "    mov es, cx",
+    "    xor eax, eax",
+    "    xor ebx, ebx",
+    "    xor ecx, ecx",
+    "    xor edx, edx",
+    "    xor esi, esi",
+    "    xor edi, edi",
+    "    xor ebp, ebp",
+    "    xor r8d, r8d",
+    "    xor r9d, r9d",
+    "    xor r10d, r10d",
+    "    xor r11d, r11d",
+    "    xor r12d, r12d",
+    "    xor r13d, r13d",
+    "    xor r14d, r14d",
+    "    xor r15d, r15d",
+    "    iretq",
+    "",
+    ".global usermode_exit",
+    "usermode_exit:",
+    "    mov rsp, rdi",
+    "    mov rax, rsi",
+    "    popfq",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop rbp",
+    "    pop rbx",
+    "    ret",
+);
+
+extern "C" {
+    fn usermode_enter(entry: u64, user_stack: u64, code_selector: u64, data_selector: u64, saved_rsp: *mut u64) -> u64;
+    fn usermode_exit(saved_rsp: u64, code: u64) -> !;
+}
+
+// 유저 프로그램을 시작한 커널 스택의 위치. 0이면 실행 중인 유저 프로그램이 없습니다.
+static KERNEL_RSP: AtomicU64 = AtomicU64::new(0);
+// 유저 프로그램을 끝낸 예외의 벡터
+static FAULT_VECTOR: AtomicU8 = AtomicU8::new(NO_FAULT);
+const NO_FAULT: u8 = u8::MAX;
+
+/// 유저 프로그램이 예외를 일으켜 강제로 끝났습니다.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserFault {
+    pub vector: u8,
+}
+
+/// ring 3에서 `entry`부터 실행하고, 프로그램이 `exit_to_kernel`로 끝나면 그 종료 코드를 돌려줍니다.
+/// 프로그램이 예외(page fault, #GP, #UD 등)를 일으키면 커널은 멈추지 않고 그 프로그램만 끝내며 `UserFault`를 돌려줍니다.
+/// 한 번에 하나의 유저 프로그램만 실행할 수 있습니다.
+///
+/// # Safety
+/// `entry`의 코드와 `stack_top` 아래의 스택은 USER_ACCESSIBLE로 매핑되어 있어야 합니다.
+/// 그렇지 않으면 유저 모드에 들어가자마자 page fault가 발생합니다.
+pub unsafe fn run(entry: VirtAddr, stack_top: VirtAddr) -> Result<u64, UserFault> {
+    assert_eq!(KERNEL_RSP.load(Ordering::Relaxed), 0, "a user program is already running");
+
+    let selectors = gdt::selectors();
+    // 함수가 call로 불렸을 때처럼 진입 시점의 rsp + 8이 16바이트로 정렬되게 합니다.
+    let user_stack = (stack_top.as_u64() & !0xF) - 8;
+    // AtomicU64는 u64와 메모리 표현이 같으므로 진입 코드가 직접 씁니다.
+    let code = usermode_enter(
+        entry.as_u64(),
+        user_stack,
+        u64::from(selectors.user_code_selector.0),
+        u64::from(selectors.user_data_selector.0),
+        &KERNEL_RSP as *const AtomicU64 as *mut u64,
+    );
+
+    KERNEL_RSP.store(0, Ordering::Relaxed);
+    DS::set_reg(selectors.data_selector);
+    ES::set_reg(selectors.data_selector);
+    match FAULT_VECTOR.swap(NO_FAULT, Ordering::Relaxed) {
+        NO_FAULT => Ok(code),
+        vector => Err(UserFault { vector }),
+    }
+}
+
+/// 실행 중인 유저 프로그램을 끝내고 `run`을 호출한 곳으로 `code`를 돌려줍니다.
+///
+/// # Safety
+/// 유저 모드에서 들어온 인터럽트나 시스템 콜 핸들러 안에서 호출해야 합니다.
+pub unsafe fn exit_to_kernel(code: u64) -> ! {
+    let saved_rsp = KERNEL_RSP.load(Ordering::Relaxed);
+    assert_ne!(saved_rsp, 0, "no user program is running");
+    usermode_exit(saved_rsp, code)
+}
+
+/// 예외를 일으킨 유저 프로그램을 끝냅니다. `run`은 `Err(UserFault { vector })`를 돌려줍니다.
+///
+/// # Safety
+/// 유저 모드에서 일어난 예외의 핸들러 안에서 호출해야 합니다.
+pub unsafe fn kill(vector: u8) -> ! {
+    FAULT_VECTOR.store(vector, Ordering::Relaxed);
+    exit_to_kernel(0)
+}
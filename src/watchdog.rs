@@ -0,0 +1,181 @@
+//! NMI 처리와 멈춤 감지 워치독
+//!
+//! 인터럽트를 끈 채로 spin lock을 기다리며 deadlock에 빠지면 타이머 인터럽트도 들어오지 않으므로
+//! 커널은 아무 출력 없이 멈춥니다. NMI는 인터럽트 플래그와 상관없이 전달되므로, 주기적으로 NMI를 받아
+//! 그 사이에 타이머 틱이 증가했는지 확인하면 이런 멈춤을 알아챌 수 있습니다.
+//!
+//! Local APIC 타이머는 NMI로 전달할 수 없고, 성능 카운터는 QEMU(TCG)가 에뮬레이션하지 않습니다.
+//! 그래서 HPET 비교기를 I/O APIC의 남는 입력에 연결하고, 그 입력을 NMI로 전달하도록 설정합니다.
+//! 멈춤을 발견하면 멈춘 지점의 레지스터와 backtrace를 SERIAL1의 잠금 없이 시리얼 포트로 출력합니다.
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::idt::InterruptDescriptorTable;
+use x86_64::VirtAddr;
+use crate::backtrace::Backtrace;
+use crate::hpet::{self, HpetError};
+use crate::monitor::{trap_entry, TrapFrame};
+use crate::gdt::IstStack;
+use crate::{apic, interrupt_stats, serial, time};
+
+const NMI_VECTOR: u8 = 2;
+// 워치독이 NMI를 보내는 간격
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+// 0~15번 입력은 ISA IRQ가 사용하므로 16번 이상의 입력에 연결합니다.
+const FIRST_FREE_GSI: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogError {
+    /// I/O APIC로 NMI를 보내야 하므로 APIC가 켜져 있어야 합니다.
+    ApicNotActive,
+    /// HPET가 초기화되지 않았습니다.
+    HpetNotFound,
+    Hpet(HpetError),
+    /// HPET 비교기를 연결할 수 있는 I/O APIC 입력이 없습니다.
+    NoNmiRoute,
+}
+
+trap_entry!("watchdog_nmi_entry", 2, "watchdog_nmi");
+
+extern "C" {
+    fn watchdog_nmi_entry();
+}
+
+/// NMI 진입 코드를 별도의 IST 스택과 함께 등록합니다.
+pub fn register(idt: &mut InterruptDescriptorTable) {
+    unsafe {
+        idt.non_maskable_interrupt
+            .set_handler_addr(VirtAddr::new(watchdog_nmi_entry as usize as u64))
+            .set_stack_index(IstStack::Nmi.index());
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// 멈췄다고 판단하기 전까지 틱이 증가하지 않은 채로 지나가도 되는 확인 횟수
+static ALLOWED_CHECKS: AtomicU32 = AtomicU32::new(0);
+static STALLED_CHECKS: AtomicU32 = AtomicU32::new(0);
+static LAST_TICKS: AtomicU64 = AtomicU64::new(0);
+static TOUCHED: AtomicBool = AtomicBool::new(false);
+// 같은 멈춤을 여러 번 보고하지 않습니다. 틱이 다시 증가하면 지워집니다.
+static REPORTED: AtomicBool = AtomicBool::new(false);
+static TIMER: AtomicU8 = AtomicU8::new(0);
+static GSI: AtomicU32 = AtomicU32::new(0);
+
+/// `timeout` 동안 타이머 틱이 증가하지 않으면 멈춘 것으로 보고 보고서를 출력하는 워치독을 켭니다.
+pub fn start(timeout: Duration) -> Result<(), WatchdogError> {
+    if !apic::is_active() {
+        return Err(WatchdogError::ApicNotActive);
+    }
+    let hpet = hpet::hpet().ok_or(WatchdogError::HpetNotFound)?;
+    // 마지막 비교기를 사용합니다. 0번 비교기는 legacy replacement로 PIT와 RTC를 대신할 때 쓰입니다.
+    let timer = hpet.timers() - 1;
+    let routes = hpet.route_capabilities(timer).map_err(WatchdogError::Hpet)?;
+    let gsi = (FIRST_FREE_GSI..32).find(|gsi| routes & (1 << gsi) != 0).ok_or(WatchdogError::NoNmiRoute)?;
+
+    let checks = (timeout.as_millis() / CHECK_INTERVAL.as_millis()).max(2) as u32;
+    without_interrupts(|| {
+        ALLOWED_CHECKS.store(checks, Ordering::Relaxed);
+        STALLED_CHECKS.store(0, Ordering::Relaxed);
+        LAST_TICKS.store(time::ticks(), Ordering::Relaxed);
+        REPORTED.store(false, Ordering::Relaxed);
+        TIMER.store(timer, Ordering::Relaxed);
+        GSI.store(gsi, Ordering::Relaxed);
+        ENABLED.store(true, Ordering::Release);
+
+        if !apic::route_gsi_to_nmi(gsi) {
+            ENABLED.store(false, Ordering::Relaxed);
+            return Err(WatchdogError::NoNmiRoute);
+        }
+        hpet.start_periodic(timer, gsi, CHECK_INTERVAL).map_err(|error| {
+            ENABLED.store(false, Ordering::Relaxed);
+            apic::mask_gsi(gsi);
+            WatchdogError::Hpet(error)
+        })
+    })
+}
+
+pub fn stop() {
+    if ENABLED.swap(false, Ordering::Relaxed) {
+        if let Some(hpet) = hpet::hpet() {
+            hpet.stop(TIMER.load(Ordering::Relaxed));
+        }
+        apic::mask_gsi(GSI.load(Ordering::Relaxed));
+    }
+}
+
+pub fn is_running() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// 인터럽트를 끈 채로 오래 기다려야 하는 코드(모니터 등)가 멈춘 것이 아님을 워치독에 알립니다.
+pub fn touch() {
+    TOUCHED.store(true, Ordering::Relaxed);
+}
+
+/*
+    NMI 핸들러
+
+    NMI는 어떤 코드든, 심지어 잠금을 쥐고 있는 도중에도 끼어들 수 있으므로 여기서는 어떤 잠금도 잡지 않습니다.
+    (println!이나 serial_println!을 쓰면 멈춘 코드가 쥔 WRITER/SERIAL1을 기다리며 같이 멈춥니다.)
+    워치독이 보낸 NMI인지 다른 장치가 보낸 NMI인지 구분할 방법이 없으므로, 워치독이 켜져 있으면 모든 NMI를 확인 시점으로 씁니다.
+*/
+#[no_mangle]
+extern "C" fn watchdog_nmi(frame: &mut TrapFrame, _vector: u64) {
+    let entry = interrupt_stats::enter();
+    if ENABLED.load(Ordering::Acquire) {
+        check(frame);
+    } else {
+        serial::emergency_print(format_args!(
+            "NMI at {:#x}\n",
+            frame.stack_frame.instruction_pointer.as_u64(),
+        ));
+    }
+    interrupt_stats::record(NMI_VECTOR, entry);
+}
+
+fn check(frame: &TrapFrame) {
+    let ticks = time::ticks();
+    let previous = LAST_TICKS.swap(ticks, Ordering::Relaxed);
+    if ticks != previous || TOUCHED.swap(false, Ordering::Relaxed) {
+        STALLED_CHECKS.store(0, Ordering::Relaxed);
+        REPORTED.store(false, Ordering::Relaxed);
+        return;
+    }
+
+    let stalled = STALLED_CHECKS.fetch_add(1, Ordering::Relaxed) + 1;
+    if stalled >= ALLOWED_CHECKS.load(Ordering::Relaxed) && !REPORTED.swap(true, Ordering::Relaxed) {
+        let stack_frame = &frame.stack_frame;
+        let backtrace = Backtrace::new(
+            stack_frame.instruction_pointer.as_u64(),
+            stack_frame.stack_pointer.as_u64(),
+            frame.registers.rbp,
+        );
+        serial::emergency_print(format_args!(
+            "\nWATCHDOG: no timer tick for {} ms (stuck at tick {})\n{}Backtrace:\n{}",
+            u128::from(stalled) * CHECK_INTERVAL.as_millis(),
+            ticks,
+            frame,
+            backtrace,
+        ));
+    }
+}
+
+#[test_case]
+fn test_nmis_arrive_until_stopped() {
+    // 테스트 커널도 init_memory에서 HPET와 APIC를 켜므로 워치독을 켤 수 있어야 합니다.
+    let before = interrupt_stats::stats(NMI_VECTOR).count;
+    start(Duration::from_secs(1)).expect("watchdog did not start");
+    assert!(is_running());
+
+    // 100ms마다 NMI가 오지만 틱이 계속 증가하므로 멈춤으로 보고하지는 않습니다.
+    time::sleep(Duration::from_millis(350));
+    let running = interrupt_stats::stats(NMI_VECTOR).count;
+    assert!(running >= before + 2, "only {} NMIs", running - before);
+    assert!(!REPORTED.load(Ordering::Relaxed));
+
+    stop();
+    assert!(!is_running());
+    let stopped = interrupt_stats::stats(NMI_VECTOR).count;
+    time::sleep(Duration::from_millis(250));
+    assert_eq!(interrupt_stats::stats(NMI_VECTOR).count, stopped);
+}
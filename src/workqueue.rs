@@ -0,0 +1,111 @@
+//! 지연 작업 큐 (bottom half)
+//!
+//! 인터럽트 핸들러는 인터럽트가 꺼진 채로 실행되므로 오래 걸리는 일을 하면 다른 인터럽트가 늦어집니다.
+//! 핸들러는 장치에서 꼭 바로 읽어야 하는 값만 읽고, 나머지 처리는 작은 작업으로 만들어 이 큐에 넣습니다.
+//! 큐에 쌓인 작업은 나중에 CPU가 쉬려고 할 때(`idle`, `input::wait`) 인터럽트가 켜진 상태에서 순서대로 실행됩니다.
+//! `hlt_loop`와 `time::sleep`도 `idle`로 쉬므로, 커널이 어디서 기다리든 작업이 밀리지 않습니다.
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::{self, without_interrupts};
+use crate::ring_buffer::RingBuffer;
+
+const QUEUE_SIZE: usize = 256;
+
+/// 작업 함수. 인터럽트 핸들러에서는 할당할 수 없으므로 `Box`에 담은 클로저 대신 함수와 `usize` 인자 하나를 함께 넘깁니다.
+pub type WorkFn = fn(usize);
+
+#[derive(Clone, Copy)]
+struct Work {
+    func: WorkFn,
+    arg: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFull;
+
+static QUEUE: Mutex<RingBuffer<Work, QUEUE_SIZE>> = Mutex::new(RingBuffer::new());
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+// run_pending이 중첩해서 호출되면 작업의 순서가 뒤바뀔 수 있으므로 한 번에 하나만 실행합니다.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// 작업을 큐에 넣습니다. 인터럽트 핸들러 안에서도 호출할 수 있습니다.
+pub fn schedule(func: WorkFn, arg: usize) -> Result<(), QueueFull> {
+    let result = without_interrupts(|| QUEUE.lock().push(Work { func, arg }));
+    result.map_err(|_| {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        QueueFull
+    })
+}
+
+pub fn is_empty() -> bool {
+    without_interrupts(|| QUEUE.lock().is_empty())
+}
+
+/// 큐에 쌓인 작업을 모두 실행하고 실행한 수를 돌려줍니다. 인터럽트 핸들러 밖에서 호출해야 합니다.
+pub fn run_pending() -> usize {
+    if RUNNING.swap(true, Ordering::Acquire) {
+        return 0;
+    }
+
+    let mut count = 0;
+    // 작업을 실행하는 동안에는 큐를 잠그지 않으므로, 작업이나 핸들러가 새 작업을 넣을 수 있습니다.
+    while let Some(work) = without_interrupts(|| QUEUE.lock().pop()) {
+        (work.func)(work.arg);
+        count += 1;
+    }
+
+    RUNNING.store(false, Ordering::Release);
+    count
+}
+
+/// 쌓인 작업을 실행한 뒤 다음 인터럽트가 올 때까지 CPU를 쉬게 합니다. 기다리는 루프에서 hlt 대신 부릅니다.
+///
+/// 인터럽트가 꺼져 있으면 핸들러 안일 수 있으므로 작업을 실행하지 않고 hlt만 합니다.
+pub fn idle() {
+    if !interrupts::are_enabled() {
+        x86_64::instructions::hlt();
+        return;
+    }
+    run_pending();
+    // 확인과 hlt 사이에 들어온 작업을 놓친 채 잠들지 않도록 인터럽트를 끈 채 확인하고 한 번에 잠듭니다.
+    interrupts::disable();
+    if is_empty() {
+        interrupts::enable_and_hlt();
+    } else {
+        interrupts::enable();
+    }
+}
+
+/// 큐가 가득 차서 버려진 작업의 수
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+#[test_case]
+fn test_run_pending_in_order() {
+    static LAST: AtomicUsize = AtomicUsize::new(0);
+    fn record(arg: usize) {
+        // 앞의 작업이 먼저 실행되었는지 확인합니다.
+        assert_eq!(LAST.swap(arg, Ordering::Relaxed), arg - 1);
+    }
+
+    run_pending();
+    LAST.store(0, Ordering::Relaxed);
+    for arg in 1..=3 {
+        schedule(record, arg).unwrap();
+    }
+    assert!(run_pending() >= 3);
+    assert_eq!(LAST.load(Ordering::Relaxed), 3);
+}
+
+#[test_case]
+fn test_sleep_runs_pending_work() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    fn mark(_: usize) {
+        DONE.store(true, Ordering::Relaxed);
+    }
+
+    schedule(mark, 0).unwrap();
+    crate::time::sleep(core::time::Duration::from_millis(2));
+    assert!(DONE.load(Ordering::Relaxed));
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// main과 같이 init_memory까지 거쳐서 ACPI, HPET, APIC가 모두 켜진 상태를 확인합니다.
+// QEMU의 기본 머신에는 FADT와 \_S5, HPET, I/O APIC가 있으므로 없으면 실패로 봅니다.
+use blog_os::interrupts::{self, InterruptController};
+use blog_os::{acpi, time};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::time::Duration;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    blog_os::init_memory(boot_info);
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn shutdown_can_enter_s5() {
+    // power::shutdown이 에뮬레이터 포트로 넘어가지 않고 ACPI로 전원을 끄는 데 필요한 값들입니다.
+    let fadt = acpi::fadt().expect("FADT not found");
+    assert_ne!(fadt.pm1a_control_block, 0);
+    assert!(acpi::s5_sleep_types(&fadt).is_some(), "no \\_S5 in the DSDT");
+}
+
+#[test_case]
+fn timer_ticks_through_apic() {
+    assert_eq!(interrupts::controller(), InterruptController::Apic);
+    // IRQ0이 override된 GSI로 제대로 연결되어 있어야 틱이 증가하고 sleep이 끝납니다.
+    let before = time::ticks();
+    time::sleep(Duration::from_millis(20));
+    assert!(time::ticks() > before);
+}
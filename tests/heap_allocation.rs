@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use blog_os::allocator::{self, HEAP_SIZE};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    unsafe { blog_os::memory::init(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn simple_allocation() {
+    let heap_value_1 = Box::new(41);
+    let heap_value_2 = Box::new(13);
+    assert_eq!(*heap_value_1, 41);
+    assert_eq!(*heap_value_2, 13);
+}
+
+#[test_case]
+fn large_vec() {
+    let n = 1000;
+    let mut vec = Vec::new();
+    for i in 0..n {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+// 해제한 메모리를 다시 쓰지 않으면 힙 크기보다 많이 할당할 때 메모리가 모자랍니다.
+#[test_case]
+fn many_boxes() {
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+}
+
+#[test_case]
+fn strings_and_maps() {
+    let mut map = BTreeMap::new();
+    for i in 0..100 {
+        map.insert(i, alloc::format!("value{}", i));
+    }
+    assert_eq!(map[&42], "value42");
+}
+
+// 모두 해제하고 나면 어떤 할당기든 처음만큼의 빈 공간을 다시 나눠 줄 수 있습니다.
+#[test_case]
+fn freed_memory_is_reusable() {
+    let before = allocator::stats();
+    let boxes: Vec<Box<[u8; 100]>> = (0..100).map(|_| Box::new([0; 100])).collect();
+    assert_eq!(allocator::stats().used, before.used + 100 * 100 + boxes.capacity() * 8);
+    drop(boxes);
+    let after = allocator::stats();
+    assert_eq!((after.used, after.free.bytes), (before.used, before.free.bytes));
+}
@@ -0,0 +1,23 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use blog_os::{exit_qemu, serial_print, serial_println, test_support, QemuExitCode};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("page_fault::page_fault_is_reported...\t");
+
+    blog_os::init();
+
+    // 매핑되지 않은 주소에 씁니다.
+    unsafe { *(0xdeadbeaf000 as *mut u64) = 42; }
+
+    serial_println!("[no page fault]");
+    exit_qemu(QemuExitCode::Failed);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_support::expect_panic(info, &["EXCEPTION: PAGE FAULT", "0xdeadbeaf000"])
+}
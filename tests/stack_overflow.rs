@@ -1,48 +1,15 @@
 #![no_std]
 #![no_main]
-#![feature(abi_x86_interrupt)]
 
 use core::panic::PanicInfo;
-use blog_os::serial_print;
-
-#[panic_handler]
-fn panic(info: &PanicInfo) -> ! {
-    blog_os::test_panic_handler(info)
-}
-
-use blog_os::{exit_qemu, QemuExitCode, serial_println};
-use x86_64::structures::idt::InterruptStackFrame;
-
-extern "x86-interrupt" fn test_double_fault_handler(
-    _stack_frame: InterruptStackFrame,
-    _error_code: u64,
-) -> ! {
-    serial_println!("[ok]");
-    exit_qemu(QemuExitCode::Success);
-    loop {}
-}
-
-use lazy_static::lazy_static;
-use x86_64::structures::idt::InterruptDescriptorTable;
-
-lazy_static! {
-    static ref TEST_IDT: InterruptDescriptorTable = {
-        let mut idt = InterruptDescriptorTable::new();
-        unsafe {
-            idt.double_fault
-                .set_handler_fn(test_double_fault_handler)
-                .set_stack_index(blog_os::gdt::DOUBLE_FAULT_IST_INDEX);
-        }
-        idt
-    };
-}
+use blog_os::{serial_print, test_support};
 
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
-    serial_print!("stack_overflow::stack_overflow...\t");
+    serial_print!("stack_overflow::stack_overflow_is_reported...\t");
 
-    blog_os::gdt::init();
-    init_test_idt();
+    // 부트로더의 커널 스택 아래에는 매핑되지 않은 guard page가 있으므로 넘치는 순간 page fault가 납니다.
+    blog_os::init();
 
     // trigger a stack overflow
     stack_overflow();
@@ -56,6 +23,7 @@ fn stack_overflow() {
     volatile::Volatile::new(0).read(); // prevent tail recursion optimizations
 }
 
-pub fn init_test_idt() {
-    TEST_IDT.load();
-}
\ No newline at end of file
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_support::expect_panic(info, &["kernel stack overflow on the kernel stack", "Depth reached"])
+}
@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// 작은 기계어 프로그램을 USER_ACCESSIBLE 페이지에 올려 ring 3에서 실행합니다.
+// 시스템 콜(SYSCALL, int 0x80)로 커널에 들어왔다가 돌아가는 경로와, 예외를 일으킨 프로그램만 끝나는지 확인합니다.
+use blog_os::frame_allocator::BitmapFrameAllocator;
+use blog_os::memory;
+use blog_os::usermode::{self, UserFault};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::structures::paging::{FrameDeallocator, Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    blog_os::init_memory(boot_info);
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// 다른 매핑과 겹치지 않는 하위 절반의 주소
+const CODE: u64 = 0x5555_0000_0000;
+const STACK: u64 = 0x5555_0001_0000;
+
+/// 코드 페이지(유저가 읽고 실행만 할 수 있음)와 스택 페이지를 매핑해서 `code`를 실행하고 매핑을 지웁니다.
+fn run(code: &[u8]) -> Result<u64, UserFault> {
+    let code_page = Page::containing_address(VirtAddr::new(CODE));
+    let stack_page = Page::containing_address(VirtAddr::new(STACK));
+    let code_frame = memory::map(code_page, PageTableFlags::USER_ACCESSIBLE).unwrap();
+    memory::map(stack_page, PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE).unwrap();
+
+    // 코드 페이지는 쓰기 금지이므로 물리 메모리 매핑으로 씁니다.
+    let target = memory::phys_to_virt(code_frame.start_address()).unwrap();
+    unsafe { core::ptr::copy_nonoverlapping(code.as_ptr(), target.as_mut_ptr::<u8>(), code.len()) };
+    let result = unsafe { usermode::run(VirtAddr::new(CODE), VirtAddr::new(STACK + 4096)) };
+
+    for page in [code_page, stack_page] {
+        let frame = memory::unmap(page).unwrap();
+        unsafe { BitmapFrameAllocator.deallocate_frame(frame) };
+    }
+    result
+}
+
+const MESSAGE: &[u8] = b"hello from ring 3\n";
+
+#[test_case]
+fn syscall_write_then_int80_exit() {
+    #[rustfmt::skip]
+    let mut program = [
+        0x48, 0x8D, 0x35, 0x1A, 0x00, 0x00, 0x00,   // lea rsi, [rip + 26]  (메시지)
+        0xBF, 0x01, 0x00, 0x00, 0x00,               // mov edi, 1           (화면)
+        0xBA, MESSAGE.len() as u8, 0x00, 0x00, 0x00, // mov edx, len
+        0xB8, 0x01, 0x00, 0x00, 0x00,               // mov eax, 1           (write)
+        0x0F, 0x05,                                 // syscall
+        0x48, 0x89, 0xC7,                           // mov rdi, rax
+        0x31, 0xC0,                                 // xor eax, eax         (exit)
+        0xCD, 0x80,                                 // int 0x80
+        0x0F, 0x0B,                                 // ud2 (exit가 돌아오면 실패)
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    program[33..].copy_from_slice(MESSAGE);
+
+    // exit 코드는 write가 돌려준 길이입니다.
+    assert_eq!(run(&program), Ok(MESSAGE.len() as u64));
+    // 한 번 끝난 뒤에도 다시 실행할 수 있습니다.
+    assert_eq!(run(&program), Ok(MESSAGE.len() as u64));
+}
+
+#[test_case]
+fn faults_end_only_the_program() {
+    // hlt는 특권 명령입니다.
+    assert_eq!(run(&[0xF4]), Err(UserFault { vector: 13 }));
+    assert_eq!(run(&[0x0F, 0x0B]), Err(UserFault { vector: 6 }));
+    // mov [rip], al: 쓰기 금지인 코드 페이지에 씁니다.
+    assert_eq!(run(&[0x88, 0x05, 0x00, 0x00, 0x00, 0x00]), Err(UserFault { vector: 14 }));
+}